@@ -0,0 +1,91 @@
+//! Throughput of the global-lock cache versus the sharded cache under parallel load.
+//!
+//! Run with `cargo bench --features cache --bench cache`.
+
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nano_image_server::cache::Cache;
+use nano_image_server::cache::s3fifo::S3Fifo;
+use nano_image_server::cache::sharded::ShardedS3Fifo;
+
+const KEYS: u64 = 10_000;
+const VALUE_SIZE: usize = 4 * 1024;
+// Room for roughly a third of the key space
+const MAX_BYTES: usize = (KEYS as usize / 3) * VALUE_SIZE;
+const OPS_PER_THREAD: usize = 200_000;
+const SHARDS: usize = 16;
+
+trait Bench: Send + Sync {
+    fn get_or_insert(&self, key: u64);
+}
+
+impl Bench for RwLock<S3Fifo<u64, Vec<u8>>> {
+    fn get_or_insert(&self, key: u64) {
+        if self.read().unwrap().get(&key).cloned().is_none() {
+            self.write().unwrap().insert(key, vec![0; VALUE_SIZE]);
+        }
+    }
+}
+
+impl Bench for ShardedS3Fifo<u64, Vec<u8>> {
+    fn get_or_insert(&self, key: u64) {
+        if self.get(&key).is_none() {
+            self.insert(key, vec![0; VALUE_SIZE]);
+        }
+    }
+}
+
+// Skewed key stream so that some keys are hot, like real image traffic
+fn next_key(state: &mut u64) -> u64 {
+    *state = state
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    let r = (*state >> 33) % KEYS;
+    (r * r) / KEYS
+}
+
+fn run(cache: Arc<dyn Bench>, threads: usize) -> Duration {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                let mut state = t as u64 + 1;
+                for _ in 0..OPS_PER_THREAD {
+                    cache.get_or_insert(next_key(&mut state));
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let max_threads = thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .max(2);
+
+    println!(
+        "{:>8} {:>18} {:>18}",
+        "threads", "global lock ops/s", "sharded ops/s"
+    );
+
+    let mut threads = 1;
+    while threads <= max_threads {
+        let global: Arc<dyn Bench> = Arc::new(RwLock::new(S3Fifo::new(MAX_BYTES)));
+        let sharded: Arc<dyn Bench> = Arc::new(ShardedS3Fifo::new(MAX_BYTES, SHARDS));
+
+        let ops = (threads * OPS_PER_THREAD) as f64;
+        let global_rate = ops / run(global, threads).as_secs_f64();
+        let sharded_rate = ops / run(sharded, threads).as_secs_f64();
+
+        println!("{threads:>8} {global_rate:>18.0} {sharded_rate:>18.0}");
+        threads *= 2;
+    }
+}
@@ -1,22 +1,116 @@
-#[cfg(feature = "tls")]
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::time::Duration;
+
+#[cfg(feature = "processing")]
+use crate::processing::ProcessingLimits;
+use crate::server::listener::SocketOptions;
+#[cfg(feature = "tls")]
+use crate::server::tls::{ClientAuth, TlsVersion};
 pub struct Args {
     pub port: u16,
+    /// Addresses to listen on, bare IPs given with `--bind` use `--port`
+    pub bind: Vec<SocketAddr>,
+    #[cfg(all(unix, not(feature = "tls")))]
+    pub unix_socket: Option<PathBuf>,
+    #[cfg(all(unix, not(feature = "tls")))]
+    pub unix_socket_mode: u32,
     pub base_url: Option<String>,
+    /// How long in-flight connections may take to finish after SIGINT / SIGTERM
+    pub drain_timeout: Duration,
+    /// Tuning of the TCP listeners
+    pub socket: SocketOptions,
+    pub root: PathBuf,
+    /// URL prefixes served from their own directory, e.g. `avatars` -> `/srv/avatars`
+    pub mounts: Vec<(String, PathBuf)>,
+    #[cfg(feature = "tls")]
+    pub cert_path: PathBuf,
+    /// Folder of per-hostname certificate folders selected by SNI
+    #[cfg(feature = "tls")]
+    pub sni_dir: Option<PathBuf>,
+    /// How often to check the certificate files for changes, `None` only reloads on SIGHUP
+    #[cfg(feature = "tls")]
+    pub cert_reload_interval: Option<Duration>,
+    /// Port of a plain HTTP listener on the `--bind` addresses that redirects to HTTPS
+    #[cfg(feature = "tls")]
+    pub http_port: Option<u16>,
+    #[cfg(feature = "tls")]
+    pub acme_challenge_dir: Option<PathBuf>,
+    #[cfg(feature = "tls")]
+    pub client_ca: Option<PathBuf>,
     #[cfg(feature = "tls")]
-    pub cert_path: Option<PathBuf>,
+    pub client_auth: ClientAuth,
+    #[cfg(feature = "tls")]
+    pub tls_min_version: TlsVersion,
+    /// Cipher suites to offer, the rustls defaults when empty
+    #[cfg(feature = "tls")]
+    pub cipher_suites: Vec<String>,
+    #[cfg(feature = "tls")]
+    pub alpn: Vec<String>,
+    #[cfg(feature = "cache")]
+    pub cache_max_bytes: usize,
+    #[cfg(feature = "cache")]
+    pub cache_shards: usize,
     #[cfg(feature = "cache")]
-    pub cache_capacity: usize,
+    pub cache_max_file_size: u64,
+    /// Pixel and blur bounds of processed images
+    #[cfg(feature = "processing")]
+    pub processing_limits: ProcessingLimits,
+    #[cfg(feature = "wasm")]
+    pub plugin_dir: Option<PathBuf>,
+    #[cfg(feature = "wasm")]
+    pub plugin_fuel: u64,
+    #[cfg(feature = "wasm")]
+    pub plugin_memory: usize,
 }
 
 impl Args {
     pub fn parse() -> Args {
         let mut port: u16 = 8000;
         let mut base_url: Option<String> = None;
+        let mut drain_timeout = Duration::from_secs(30);
+        let mut socket = SocketOptions::default();
+        let mut bind: Vec<String> = Vec::new();
+        #[cfg(all(unix, not(feature = "tls")))]
+        let mut unix_socket: Option<PathBuf> = None;
+        #[cfg(all(unix, not(feature = "tls")))]
+        let mut unix_socket_mode: u32 = 0o660;
+        let mut root = PathBuf::from("./images/");
+        let mut mounts: Vec<(String, PathBuf)> = Vec::new();
         #[cfg(feature = "tls")]
         let mut cert_path: Option<PathBuf> = None;
+        #[cfg(feature = "tls")]
+        let mut sni_dir: Option<PathBuf> = None;
+        #[cfg(feature = "tls")]
+        let mut cert_reload_interval = Some(Duration::from_secs(60));
+        #[cfg(feature = "tls")]
+        let mut http_port: Option<u16> = None;
+        #[cfg(feature = "tls")]
+        let mut acme_challenge_dir: Option<PathBuf> = None;
+        #[cfg(feature = "tls")]
+        let mut client_ca: Option<PathBuf> = None;
+        #[cfg(feature = "tls")]
+        let mut client_auth: Option<ClientAuth> = None;
+        #[cfg(feature = "tls")]
+        let mut tls_min_version = TlsVersion::Tls12;
+        #[cfg(feature = "tls")]
+        let mut cipher_suites: Vec<String> = Vec::new();
+        #[cfg(feature = "tls")]
+        let mut alpn = vec!["h2".to_string(), "http/1.1".to_string()];
         #[cfg(feature = "cache")]
-        let mut cache_capacity: usize = 100;
+        let mut cache_max_bytes: usize = 256 * 1024 * 1024;
+        #[cfg(feature = "cache")]
+        let mut cache_shards: usize = 16;
+        #[cfg(feature = "cache")]
+        let mut cache_max_file_size: u64 = 8 * 1024 * 1024;
+        #[cfg(feature = "processing")]
+        let mut processing_limits = ProcessingLimits::default();
+        #[cfg(feature = "wasm")]
+        let mut plugin_dir: Option<PathBuf> = None;
+        #[cfg(feature = "wasm")]
+        let mut plugin_fuel: u64 = 1_000_000_000;
+        #[cfg(feature = "wasm")]
+        let mut plugin_memory: usize = 256 * 1024 * 1024;
 
         let mut args = std::env::args().skip(1); // skip the binary name
 
@@ -35,34 +129,331 @@ impl Args {
                         .parse()
                         .unwrap_or_else(|_| fail("--port expects a number 0-65535"));
                 }
+                "--bind" => {
+                    bind.push(next_value(&mut args, "--bind"));
+                }
+                #[cfg(all(unix, not(feature = "tls")))]
+                "--unix-socket" => {
+                    unix_socket = Some(PathBuf::from(next_value(&mut args, "--unix-socket")));
+                }
+                #[cfg(all(unix, not(feature = "tls")))]
+                "--unix-socket-mode" => {
+                    let mode = next_value(&mut args, "--unix-socket-mode");
+                    unix_socket_mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+                        .ok()
+                        .filter(|mode| *mode <= 0o777)
+                        .unwrap_or_else(|| fail("--unix-socket-mode expects octal bits like 660"));
+                }
                 "-b" | "--base-url" => {
                     base_url = Some(next_value(&mut args, "--base-url"));
                 }
+                "--drain-timeout" => {
+                    drain_timeout = next_value(&mut args, "--drain-timeout")
+                        .parse()
+                        .map(Duration::from_secs)
+                        .unwrap_or_else(|_| fail("--drain-timeout expects a number of seconds"));
+                }
+                "--send-buffer" => {
+                    socket.send_buffer_size = Some(
+                        parse_size(&next_value(&mut args, "--send-buffer"))
+                            .unwrap_or_else(|| fail("--send-buffer expects a size like 512K")),
+                    );
+                }
+                "--recv-buffer" => {
+                    socket.recv_buffer_size = Some(
+                        parse_size(&next_value(&mut args, "--recv-buffer"))
+                            .unwrap_or_else(|| fail("--recv-buffer expects a size like 512K")),
+                    );
+                }
+                "--no-nodelay" => {
+                    socket.nodelay = false;
+                }
+                "--keepalive" => {
+                    let secs: u64 = next_value(&mut args, "--keepalive")
+                        .parse()
+                        .unwrap_or_else(|_| fail("--keepalive expects a number of seconds"));
+                    socket.keepalive = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                "--backlog" => {
+                    socket.backlog = next_value(&mut args, "--backlog")
+                        .parse()
+                        .ok()
+                        .filter(|backlog| *backlog > 0)
+                        .unwrap_or_else(|| fail("--backlog expects a positive number"));
+                }
+                #[cfg(unix)]
+                "--reuse-port" => {
+                    socket.reuse_port = true;
+                }
+                "-r" | "--root" => {
+                    root = PathBuf::from(next_value(&mut args, "--root"));
+                }
+                "-m" | "--mount" => {
+                    let mount = next_value(&mut args, "--mount");
+                    let (name, dir) = mount
+                        .split_once('=')
+                        .filter(|(name, dir)| is_mount_name(name) && !dir.is_empty())
+                        .unwrap_or_else(|| {
+                            fail("--mount expects NAME=DIR, e.g. avatars=/srv/avatars")
+                        });
+
+                    if mounts.iter().any(|(existing, _)| existing == name) {
+                        fail(&format!("--mount {name} given more than once"));
+                    }
+                    mounts.push((name.to_string(), PathBuf::from(dir)));
+                }
                 #[cfg(feature = "tls")]
                 "-c" | "--cert-path" => {
                     cert_path = Some(PathBuf::from(next_value(&mut args, "--cert-path")));
                 }
+                #[cfg(feature = "tls")]
+                "--sni-dir" => {
+                    sni_dir = Some(PathBuf::from(next_value(&mut args, "--sni-dir")));
+                }
+                #[cfg(feature = "tls")]
+                "--cert-reload-interval" => {
+                    let secs: u64 = next_value(&mut args, "--cert-reload-interval")
+                        .parse()
+                        .unwrap_or_else(|_| {
+                            fail("--cert-reload-interval expects a number of seconds")
+                        });
+                    cert_reload_interval = (secs > 0).then(|| Duration::from_secs(secs));
+                }
+                #[cfg(feature = "tls")]
+                "--http-port" => {
+                    http_port = Some(
+                        next_value(&mut args, "--http-port")
+                            .parse()
+                            .unwrap_or_else(|_| fail("--http-port expects a number 0-65535")),
+                    );
+                }
+                #[cfg(feature = "tls")]
+                "--acme-challenge-dir" => {
+                    acme_challenge_dir =
+                        Some(PathBuf::from(next_value(&mut args, "--acme-challenge-dir")));
+                }
+                #[cfg(feature = "tls")]
+                "--client-ca" => {
+                    client_ca = Some(PathBuf::from(next_value(&mut args, "--client-ca")));
+                }
+                #[cfg(feature = "tls")]
+                "--client-auth" => {
+                    client_auth = match next_value(&mut args, "--client-auth").as_str() {
+                        "required" => Some(ClientAuth::Required),
+                        "optional" => Some(ClientAuth::Optional),
+                        _ => fail("--client-auth expects required or optional"),
+                    };
+                }
+                #[cfg(feature = "tls")]
+                "--tls-min-version" => {
+                    tls_min_version = match next_value(&mut args, "--tls-min-version").as_str() {
+                        "1.2" => TlsVersion::Tls12,
+                        "1.3" => TlsVersion::Tls13,
+                        _ => fail("--tls-min-version expects 1.2 or 1.3"),
+                    };
+                }
+                #[cfg(feature = "tls")]
+                "--cipher-suites" => {
+                    cipher_suites = split_list(&next_value(&mut args, "--cipher-suites"));
+                }
+                #[cfg(feature = "tls")]
+                "--alpn" => {
+                    alpn = split_list(&next_value(&mut args, "--alpn"));
+                    if alpn.is_empty() {
+                        fail("--alpn expects a list like h2,http/1.1");
+                    }
+                }
                 #[cfg(feature = "cache")]
-                "--cache-capacity" => {
-                    cache_capacity = next_value(&mut args, "--cache-capacity")
+                "--cache-max-bytes" => {
+                    cache_max_bytes = parse_size(&next_value(&mut args, "--cache-max-bytes"))
+                        .unwrap_or_else(|| fail("--cache-max-bytes expects a size like 512M"));
+                }
+                #[cfg(feature = "cache")]
+                "--cache-shards" => {
+                    cache_shards = next_value(&mut args, "--cache-shards")
+                        .parse()
+                        .ok()
+                        .filter(|shards| *shards > 0)
+                        .unwrap_or_else(|| fail("--cache-shards expects a positive number"));
+                }
+                #[cfg(feature = "cache")]
+                "--cache-max-file-size" => {
+                    cache_max_file_size =
+                        parse_size(&next_value(&mut args, "--cache-max-file-size"))
+                            .unwrap_or_else(|| fail("--cache-max-file-size expects a size like 8M"))
+                            as u64;
+                }
+                #[cfg(feature = "processing")]
+                "--max-pixels" => {
+                    processing_limits.max_pixels = next_value(&mut args, "--max-pixels")
+                        .parse()
+                        .ok()
+                        .filter(|pixels| *pixels > 0)
+                        .unwrap_or_else(|| fail("--max-pixels expects a positive number"));
+                }
+                #[cfg(feature = "processing")]
+                "--max-blur" => {
+                    processing_limits.max_blur_sigma = next_value(&mut args, "--max-blur")
                         .parse()
-                        .unwrap_or_else(|_| fail("--cache-capacity expects a number"));
+                        .ok()
+                        .filter(|sigma: &f32| sigma.is_finite() && *sigma >= 0.0)
+                        .unwrap_or_else(|| fail("--max-blur expects a non-negative number"));
+                }
+                #[cfg(feature = "wasm")]
+                "--plugin-dir" => {
+                    plugin_dir = Some(PathBuf::from(next_value(&mut args, "--plugin-dir")));
+                }
+                #[cfg(feature = "wasm")]
+                "--plugin-fuel" => {
+                    plugin_fuel = next_value(&mut args, "--plugin-fuel")
+                        .parse()
+                        .unwrap_or_else(|_| fail("--plugin-fuel expects a number"));
+                }
+                #[cfg(feature = "wasm")]
+                "--plugin-memory" => {
+                    plugin_memory = parse_size(&next_value(&mut args, "--plugin-memory"))
+                        .unwrap_or_else(|| fail("--plugin-memory expects a size like 256M"));
                 }
                 other => fail(&format!("unknown argument: {other}")),
             }
         }
 
+        #[cfg(feature = "tls")]
+        let cert_path = cert_path.unwrap_or_else(|| fail("--cert-path is required for HTTPS"));
+
+        #[cfg(feature = "tls")]
+        if acme_challenge_dir.is_some() && http_port.is_none() {
+            fail("--acme-challenge-dir requires --http-port");
+        }
+
+        #[cfg(feature = "tls")]
+        if client_auth.is_some() && client_ca.is_none() {
+            fail("--client-auth requires --client-ca");
+        }
+
+        // Only a Unix socket is served when one is given without any --bind
+        #[cfg(all(unix, not(feature = "tls")))]
+        let default_bind = unix_socket.is_none();
+        #[cfg(not(all(unix, not(feature = "tls"))))]
+        let default_bind = true;
+
+        let bind = if !bind.is_empty() {
+            bind.iter()
+                .map(|addr| {
+                    parse_bind(addr, port).unwrap_or_else(|| {
+                        fail(&format!(
+                            "--bind expects an address like 0.0.0.0, [::]:8443 or ::1, got {addr}"
+                        ))
+                    })
+                })
+                .collect()
+        } else if default_bind {
+            vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)]
+        } else {
+            Vec::new()
+        };
+
         Args {
             port,
+            bind,
+            #[cfg(all(unix, not(feature = "tls")))]
+            unix_socket,
+            #[cfg(all(unix, not(feature = "tls")))]
+            unix_socket_mode,
             base_url,
+            drain_timeout,
+            socket,
+            root,
+            mounts,
             #[cfg(feature = "tls")]
             cert_path,
+            #[cfg(feature = "tls")]
+            sni_dir,
+            #[cfg(feature = "tls")]
+            cert_reload_interval,
+            #[cfg(feature = "tls")]
+            http_port,
+            #[cfg(feature = "tls")]
+            acme_challenge_dir,
+            #[cfg(feature = "tls")]
+            client_ca,
+            #[cfg(feature = "tls")]
+            client_auth: client_auth.unwrap_or(ClientAuth::Required),
+            #[cfg(feature = "tls")]
+            tls_min_version,
+            #[cfg(feature = "tls")]
+            cipher_suites,
+            #[cfg(feature = "tls")]
+            alpn,
             #[cfg(feature = "cache")]
-            cache_capacity,
+            cache_max_bytes,
+            #[cfg(feature = "cache")]
+            cache_shards,
+            #[cfg(feature = "cache")]
+            cache_max_file_size,
+            #[cfg(feature = "processing")]
+            processing_limits,
+            #[cfg(feature = "wasm")]
+            plugin_dir,
+            #[cfg(feature = "wasm")]
+            plugin_fuel,
+            #[cfg(feature = "wasm")]
+            plugin_memory,
         }
     }
 }
 
+/// Parse a byte size with an optional binary suffix, e.g. `512M`, `2G`, `64KiB` or `1048576`
+fn parse_size(value: &str) -> Option<usize> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(digits);
+    let number: usize = number.parse().ok()?;
+
+    let multiplier: usize = match suffix.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+
+    number.checked_mul(multiplier)
+}
+
+/// Parse `IP`, `[IPv6]` or `IP:PORT` / `[IPv6]:PORT`, bare addresses listen on `port`
+fn parse_bind(value: &str, port: u16) -> Option<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+
+    let ip = value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .unwrap_or(value);
+    ip.parse::<IpAddr>()
+        .ok()
+        .map(|ip| SocketAddr::new(ip, port))
+}
+
+// A mount name is a single URL path segment
+fn is_mount_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(['/', '\\']) && name != "." && name != ".."
+}
+
+// `a, b,c` -> `[a, b, c]`
+#[cfg(feature = "tls")]
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> String {
     args.next()
         .unwrap_or_else(|| fail(&format!("{flag} expects a value")))
@@ -85,15 +476,151 @@ USAGE:
 
 OPTIONS:
     -p, --port <PORT>          Port to listen on [default: 8000]
-    -b, --base-url <URL>       Base url where the app is hosted [default: localhost]",
+        --bind <ADDR>          Address to listen on, e.g. 0.0.0.0, [::] or [::1]:8443, may be
+                               repeated; [::] also accepts IPv4 [default: 127.0.0.1]
+    -b, --base-url <URL>       Base url where the app is hosted [default: localhost]
+        --drain-timeout <SECS> Time given to open connections on shutdown [default: 30]
+        --send-buffer <SIZE>   TCP send buffer of each connection, e.g. 512K [default: OS]
+        --recv-buffer <SIZE>   TCP receive buffer of each connection [default: OS]
+        --no-nodelay           Let TCP batch small writes (Nagle) instead of sending them at once
+        --keepalive <SECS>     Idle time before TCP keepalive probes, 0 disables [default: 0]
+        --backlog <N>          Pending connections queued by the kernel [default: 1024]
+    -r, --root <DIR>           Folder images are served from [default: ./images/]
+    -m, --mount <NAME=DIR>     Serve DIR under the /NAME/ prefix, may be repeated",
         name = env!("CARGO_PKG_NAME"),
         version = env!("CARGO_PKG_VERSION"),
     );
+    #[cfg(unix)]
+    eprintln!(
+        "        --reuse-port           Allow other processes to listen on the same address (SO_REUSEPORT)"
+    );
+    #[cfg(all(unix, not(feature = "tls")))]
+    eprintln!(
+        "        --unix-socket <PATH>   Also listen on a Unix socket, only it is served without --bind
+        --unix-socket-mode <MODE> Permissions of the socket file, in octal [default: 660]"
+    );
     #[cfg(feature = "tls")]
-    eprintln!("    -c, --cert-path <DIR>      Folder containing cert.pem and key.pem (PEM format)");
+    eprintln!(
+        "    -c, --cert-path <DIR>      Folder containing cert.pem and key.pem (PEM format)
+        --sni-dir <DIR>        Folder of <hostname>/cert.pem and key.pem pairs chosen by SNI,
+                               --cert-path is used for other hostnames
+        --cert-reload-interval <SECS> Check the certificate for changes, 0 only reloads on
+                               SIGHUP [default: 60]
+        --http-port <PORT>     Also listen for plain HTTP and redirect it to HTTPS
+        --acme-challenge-dir <DIR> Serve ACME HTTP-01 challenges from DIR on the HTTP port
+        --client-ca <PEM>      Verify client certificates against these CAs (mutual TLS)
+        --client-auth <MODE>   required or optional client certificates [default: required]
+        --tls-min-version <VER> Oldest TLS version accepted, 1.2 or 1.3 [default: 1.2]
+        --cipher-suites <LIST> Comma separated cipher suites, e.g.
+                               TLS13_AES_256_GCM_SHA384 [default: rustls defaults]
+        --alpn <LIST>          Comma separated ALPN protocols [default: h2,http/1.1]"
+    );
     #[cfg(feature = "cache")]
-    eprintln!("        --cache-capacity <N>   Number of images to cache [default: 100]");
+    eprintln!(
+        "        --cache-max-bytes <SIZE> Memory budget of the cache, e.g. 512M [default: 256M]
+        --cache-shards <N>     Independently locked cache shards [default: 16]
+        --cache-max-file-size <SIZE> Larger originals are streamed, not cached [default: 8M]"
+    );
+    #[cfg(feature = "processing")]
+    eprintln!(
+        "        --max-pixels <N>       Largest source or output image that is processed, in pixels
+                               [default: 40000000]
+        --max-blur <SIGMA>     Largest blur sigma accepted [default: 50]"
+    );
+    #[cfg(feature = "wasm")]
+    eprintln!(
+        "        --plugin-dir <DIR>     Folder of WebAssembly (.wasm) plugins to load at startup
+        --plugin-fuel <N>      Fuel budget per plugin call [default: 1000000000]
+        --plugin-memory <SIZE> Memory limit per plugin call [default: 256M]"
+    );
     eprintln!(
         "    -h, --help                 Print help\n    -V, --version              Print version"
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        let cases = [
+            ("1048576", Some(1048576)),
+            ("512", Some(512)),
+            ("512B", Some(512)),
+            ("64K", Some(64 * 1024)),
+            ("64KiB", Some(64 * 1024)),
+            ("512M", Some(512 * 1024 * 1024)),
+            ("512mb", Some(512 * 1024 * 1024)),
+            ("1G", Some(1024 * 1024 * 1024)),
+            ("2GiB", Some(2 * 1024 * 1024 * 1024)),
+            (" 8M ", Some(8 * 1024 * 1024)),
+            ("", None),
+            ("M", None),
+            ("1T", None),
+            ("1MM", None),
+            ("1 M", None),
+            ("1.5G", None),
+            ("-1", None),
+        ];
+
+        for (value, expected) in cases {
+            assert_eq!(parse_size(value), expected, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        assert_eq!(parse_size(&format!("{}", usize::MAX)), Some(usize::MAX));
+        assert_eq!(parse_size(&format!("{}K", usize::MAX)), None);
+        assert_eq!(parse_size(&format!("{}G", usize::MAX / 1024)), None);
+        assert_eq!(parse_size(&format!("{}0", usize::MAX)), None);
+    }
+
+    #[test]
+    fn parses_bind_addresses() {
+        let cases = [
+            ("127.0.0.1", Some("127.0.0.1:8000")),
+            ("0.0.0.0:80", Some("0.0.0.0:80")),
+            ("::1", Some("[::1]:8000")),
+            ("::", Some("[::]:8000")),
+            ("[::1]", Some("[::1]:8000")),
+            ("[::1]:80", Some("[::1]:80")),
+            ("[fe80::1]:443", Some("[fe80::1]:443")),
+            ("localhost", None),
+            ("80", None),
+            (":80", None),
+            ("127.0.0.1:", None),
+            ("127.0.0.1:70000", None),
+            ("[127.0.0.1]:80", None),
+            ("[::1", None),
+            ("::1]:80", None),
+            ("", None),
+        ];
+
+        for (value, expected) in cases {
+            let expected = expected.map(|addr| addr.parse::<SocketAddr>().unwrap());
+            assert_eq!(parse_bind(value, 8000), expected, "{value:?}");
+        }
+    }
+
+    #[test]
+    fn mount_names_are_single_path_segments() {
+        for name in ["media", "2024", "a.b", "..a", "with space"] {
+            assert!(is_mount_name(name), "{name:?}");
+        }
+        for name in ["", ".", "..", "a/b", "/media", "media/", "a\\b"] {
+            assert!(!is_mount_name(name), "{name:?}");
+        }
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn splits_lists() {
+        assert_eq!(split_list("h2,http/1.1"), ["h2", "http/1.1"]);
+        assert_eq!(split_list(" a , b,c "), ["a", "b", "c"]);
+        assert_eq!(split_list("a,,b,"), ["a", "b"]);
+        assert!(split_list("").is_empty());
+        assert!(split_list(" , ").is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use xxhash_rust::xxh3::{Xxh3, xxh3_64};
+
+use crate::error::Result;
+
+/// An image ready to be sent, with the validators used for conditional requests.
+///
+/// Cloning only bumps the refcount of the body.
+#[derive(Debug, Clone)]
+pub struct ImageBody {
+    pub content_type: String,
+    pub bytes: Bytes,
+    /// Strong validator derived from the content
+    pub etag: String,
+    /// Modification time of the source file
+    pub last_modified: Option<SystemTime>,
+}
+
+impl ImageBody {
+    pub fn new(content_type: String, bytes: Bytes, last_modified: Option<SystemTime>) -> Self {
+        let etag = etag_for(&bytes);
+        Self {
+            content_type,
+            bytes,
+            etag,
+            last_modified,
+        }
+    }
+}
+
+/// An image file on disk that has been resolved and checked but not read yet
+#[derive(Debug, Clone)]
+pub struct ImageFile {
+    pub path: PathBuf,
+    pub content_type: String,
+    pub len: u64,
+    pub last_modified: Option<SystemTime>,
+    /// Strong validator derived from the content, the same one [`ImageBody`] would carry
+    pub etag: String,
+}
+
+impl ImageFile {
+    /// Read the whole file into memory
+    pub async fn read(&self) -> Result<ImageBody> {
+        let bytes = fs::read(&self.path).await?;
+
+        Ok(ImageBody {
+            content_type: self.content_type.clone(),
+            bytes: Bytes::from(bytes),
+            etag: self.etag.clone(),
+            last_modified: self.last_modified,
+        })
+    }
+}
+
+// Past this many files the hashes are forgotten rather than growing with every file served
+const MAX_FILE_HASHES: usize = 65_536;
+
+/// Content hashes of files on disk, reused while a file keeps its size and modification time so
+/// each version of a file is only read once to be hashed
+#[derive(Default)]
+pub struct FileHashes {
+    hashes: Mutex<HashMap<PathBuf, (u64, SystemTime, String)>>,
+}
+
+impl FileHashes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Entity tag of the file at `path`, which is `len` bytes long and was last modified at
+    /// `modified`
+    pub async fn etag(
+        &self,
+        path: &Path,
+        len: u64,
+        modified: Option<SystemTime>,
+    ) -> Result<String> {
+        if let Some(modified) = modified
+            && let Some((_, _, etag)) = self
+                .hashes
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .get(path)
+                .filter(|(l, m, _)| *l == len && *m == modified)
+        {
+            return Ok(etag.clone());
+        }
+
+        let etag = hash_file(path).await?;
+
+        // Without a modification time, or if the file changed while it was read, there is
+        // nothing to tell whether the hash is still current
+        let metadata = fs::metadata(path).await?;
+        if let Some(modified) = modified
+            && metadata.len() == len
+            && metadata.modified().ok() == Some(modified)
+        {
+            let mut hashes = self.hashes.lock().unwrap_or_else(|e| e.into_inner());
+            if hashes.len() >= MAX_FILE_HASHES && !hashes.contains_key(path) {
+                hashes.clear();
+            }
+            hashes.insert(path.to_path_buf(), (len, modified, etag.clone()));
+        }
+
+        Ok(etag)
+    }
+}
+
+// Same tag as `etag_for`, computed without holding the whole file in memory
+async fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Xxh3::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(format!("\"{:016x}\"", hasher.digest()))
+}
+
+/// What a request is answered with: a body already in memory, or a file streamed from disk
+#[derive(Debug, Clone)]
+pub enum ImageResponse {
+    Buffered(ImageBody),
+    Streamed(ImageFile),
+}
+
+/// Quoted strong entity tag of `bytes`
+pub fn etag_for(bytes: &[u8]) -> String {
+    format!("\"{:016x}\"", xxh3_64(bytes))
+}
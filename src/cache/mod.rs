@@ -1,4 +1,10 @@
+use bytes::Bytes;
+
+use crate::body::ImageBody;
+
 pub mod s3fifo;
+pub mod sharded;
+pub mod single_flight;
 
 pub trait Cache<K, V> {
     fn get(&self, key: &K) -> Option<&V>;
@@ -6,4 +12,31 @@ pub trait Cache<K, V> {
     fn contains(&self, key: &K) -> bool;
     fn len(&self) -> usize;
     fn clear(&mut self);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Size of a cached value counted against the cache's byte budget
+pub trait Weighted {
+    fn weight(&self) -> usize;
+}
+
+impl Weighted for Vec<u8> {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Weighted for Bytes {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Weighted for ImageBody {
+    fn weight(&self) -> usize {
+        self.bytes.len()
+    }
 }
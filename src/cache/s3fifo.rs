@@ -1,17 +1,28 @@
-use std::collections::{HashMap, VecDeque};
-use std::hash::Hash;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{BuildHasher, Hash, RandomState};
 use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
 
+use crate::cache::Weighted;
+
+/// S3-FIFO cache bounded by the total weight of its values (bytes for image data) rather than
+/// by the number of entries.
+///
+/// Evicted entries are dropped immediately; the ghost queue only remembers a 64-bit fingerprint
+/// of their keys so a quick re-request can be admitted straight into main.
 pub struct S3Fifo<K, V> {
     cache: HashMap<K, CacheEntry<V>>,
 
     small: VecDeque<K>,
     main: VecDeque<K>,
-    ghost: VecDeque<K>,
+    ghost: VecDeque<u64>,
+    ghost_set: HashSet<u64>,
+    fingerprinter: RandomState,
+
+    small_budget: usize,
+    main_budget: usize,
 
-    small_capacity: usize,
-    main_capacity: usize,
-    ghost_capacity: usize,
+    small_bytes: usize,
+    main_bytes: usize,
 
     hits: AtomicU64,
     misses: AtomicU64,
@@ -19,6 +30,7 @@ pub struct S3Fifo<K, V> {
 
 struct CacheEntry<V> {
     value: V,
+    weight: usize,
     freq: AtomicU8,
     location: QueueLocation,
 }
@@ -27,7 +39,6 @@ struct CacheEntry<V> {
 enum QueueLocation {
     Small,
     Main,
-    Ghost,
 }
 
 #[derive(Debug, Clone)]
@@ -36,26 +47,32 @@ pub struct CacheStats {
     pub misses: u64,
     pub hit_rate: f64,
     pub size: usize,
-    pub capacity: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
 }
 
 impl<K, V> S3Fifo<K, V>
 where
     K: Hash + Eq + Clone,
+    V: Weighted,
 {
-    pub fn new(capacity: usize) -> Self {
-        let small_capacity = capacity / 10;
-        let main_capacity = capacity - small_capacity;
-        let ghost_capacity = capacity;
+    /// Create a cache holding at most `max_bytes` worth of values, 10% of which is the budget
+    /// of the small queue
+    pub fn new(max_bytes: usize) -> Self {
+        let small_budget = max_bytes / 10;
+        let main_budget = max_bytes - small_budget;
 
         Self {
             cache: HashMap::new(),
             small: VecDeque::new(),
             main: VecDeque::new(),
             ghost: VecDeque::new(),
-            small_capacity,
-            main_capacity,
-            ghost_capacity,
+            ghost_set: HashSet::new(),
+            fingerprinter: RandomState::new(),
+            small_budget,
+            main_budget,
+            small_bytes: 0,
+            main_bytes: 0,
             hits: AtomicU64::new(0),
             misses: AtomicU64::new(0),
         }
@@ -76,15 +93,42 @@ where
             misses,
             hit_rate,
             size: self.small.len() + self.main.len(),
-            capacity: self.small_capacity + self.main_capacity,
+            bytes: self.small_bytes + self.main_bytes,
+            max_bytes: self.small_budget + self.main_budget,
         }
     }
 
+    /// Weight of the largest value the cache admits, anything heavier is never cached
+    pub fn max_weight(&self) -> usize {
+        self.main_budget
+    }
+
     pub fn reset_stats(&self) {
         self.hits.store(0, Ordering::Relaxed);
         self.misses.store(0, Ordering::Relaxed);
     }
 
+    /// Value for `key` without counting a hit or miss or raising its frequency
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.cache.get(key).map(|entry| &entry.value)
+    }
+
+    fn fingerprint(&self, key: &K) -> u64 {
+        self.fingerprinter.hash_one(key)
+    }
+
+    fn enforce_small_budget(&mut self) {
+        while self.small_bytes > self.small_budget && !self.small.is_empty() {
+            self.evict_from_small();
+        }
+    }
+
+    fn enforce_main_budget(&mut self) {
+        while self.main_bytes > self.main_budget && !self.main.is_empty() {
+            self.evict_from_main();
+        }
+    }
+
     fn evict_from_small(&mut self) {
         // Once removed, read the frequency counter of the removed entry with the key
         // Read frequency without holding a mutable borrow
@@ -96,17 +140,16 @@ where
         // Push the entry again back into small
         //
         // if frequency is greater than 1 promote entry to main queue
-        // Check capacity BEFORE getting mutable borrow
-        // Mutate entry after evict_from_main is done
-        // Push the key to main
+        // Mutate entry, push the key to main and then make room in main
         while let Some(key) = self.small.pop_front() {
-            let freq = if let Some(entry) = self.cache.get(&key) {
-                entry.freq.load(Ordering::Relaxed)
+            let (freq, weight) = if let Some(entry) = self.cache.get(&key) {
+                (entry.freq.load(Ordering::Relaxed), entry.weight)
             } else {
                 continue;
             };
 
             if freq == 0 {
+                self.small_bytes -= weight;
                 self.move_to_ghost(key);
                 return;
             } else if freq == 1 {
@@ -115,15 +158,14 @@ where
                 }
                 self.small.push_back(key);
             } else {
-                if self.main.len() >= self.main_capacity {
-                    self.evict_from_main();
-                }
-
                 if let Some(entry) = self.cache.get_mut(&key) {
                     entry.freq.store(freq - 1, Ordering::Relaxed);
                     entry.location = QueueLocation::Main;
                 }
+                self.small_bytes -= weight;
+                self.main_bytes += weight;
                 self.main.push_back(key);
+                self.enforce_main_budget();
                 return;
             }
         }
@@ -131,13 +173,14 @@ where
 
     fn evict_from_main(&mut self) {
         while let Some(key) = self.main.pop_front() {
-            let freq = if let Some(entry) = self.cache.get(&key) {
-                entry.freq.load(Ordering::Relaxed)
+            let (freq, weight) = if let Some(entry) = self.cache.get(&key) {
+                (entry.freq.load(Ordering::Relaxed), entry.weight)
             } else {
                 continue;
             };
 
             if freq == 0 {
+                self.main_bytes -= weight;
                 self.move_to_ghost(key);
                 return;
             } else {
@@ -150,18 +193,39 @@ where
         }
     }
 
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = self.cache.remove(key)?;
+        match entry.location {
+            QueueLocation::Small => {
+                self.small_bytes -= entry.weight;
+                self.small.retain(|k| k != key);
+            }
+            QueueLocation::Main => {
+                self.main_bytes -= entry.weight;
+                self.main.retain(|k| k != key);
+            }
+        }
+
+        Some(entry.value)
+    }
+
     fn move_to_ghost(&mut self, key: K) {
-        if let Some(entry) = self.cache.get_mut(&key) {
-            entry.location = QueueLocation::Ghost;
-            entry.freq.store(0, Ordering::Relaxed);
+        // Dropping the entry frees the value, only the fingerprint is kept around
+        if self.cache.remove(&key).is_none() {
+            return;
+        }
 
-            self.ghost.push_back(key.clone());
+        let fingerprint = self.fingerprint(&key);
+        if self.ghost_set.insert(fingerprint) {
+            self.ghost.push_back(fingerprint);
+        }
 
-            if self.ghost.len() > self.ghost_capacity {
-                if let Some(old_ghost) = self.ghost.pop_front() {
-                    self.cache.remove(&old_ghost);
-                }
-            }
+        // Remember as many evicted keys as there are resident entries
+        let ghost_capacity = self.cache.len().max(1);
+        while self.ghost.len() > ghost_capacity
+            && let Some(old_ghost) = self.ghost.pop_front()
+        {
+            self.ghost_set.remove(&old_ghost);
         }
     }
 }
@@ -169,10 +233,11 @@ where
 impl<K, V> crate::cache::Cache<K, V> for S3Fifo<K, V>
 where
     K: Hash + Eq + Clone,
+    V: Weighted,
 {
     fn get(&self, key: &K) -> Option<&V> {
         match self.cache.get(key) {
-            Some(entry) if entry.location != QueueLocation::Ghost => {
+            Some(entry) => {
                 self.hits.fetch_add(1, Ordering::Relaxed);
 
                 let _ = entry
@@ -182,7 +247,7 @@ where
                     });
                 Some(&entry.value)
             }
-            _ => {
+            None => {
                 self.misses.fetch_add(1, Ordering::Relaxed);
                 None
             }
@@ -190,58 +255,79 @@ where
     }
 
     fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let weight = value.weight();
+
+        // Values that could never fit in main are not cached at all, nor is the value they
+        // replace kept around
+        if weight > self.main_budget {
+            return self.remove(&key);
+        }
+
         if let Some(entry) = self.cache.get_mut(&key) {
-            if entry.location != QueueLocation::Ghost {
-                let old_value = std::mem::replace(&mut entry.value, value);
-                entry.freq.store(1, Ordering::Relaxed);
-                return Some(old_value);
+            let old_weight = std::mem::replace(&mut entry.weight, weight);
+            let old_value = std::mem::replace(&mut entry.value, value);
+            entry.freq.store(1, Ordering::Relaxed);
+
+            if entry.location == QueueLocation::Small && weight > self.small_budget {
+                // Grown past what small can hold, enforcing its budget would evict the entry
+                entry.location = QueueLocation::Main;
+                self.small.retain(|k| *k != key);
+                self.small_bytes -= old_weight;
+                self.main_bytes += weight;
+                self.main.push_back(key);
+                self.enforce_main_budget();
+            } else if entry.location == QueueLocation::Small {
+                self.small_bytes = self.small_bytes - old_weight + weight;
+                self.enforce_small_budget();
+            } else {
+                self.main_bytes = self.main_bytes - old_weight + weight;
+                self.enforce_main_budget();
             }
+            return Some(old_value);
         }
 
-        if let Some(entry) = self.cache.get(&key) {
-            if entry.location == QueueLocation::Ghost {
-                self.ghost.retain(|k| k != &key);
-                self.cache.remove(&key);
-
-                if self.main.len() >= self.main_capacity {
-                    self.evict_from_main();
-                }
-
-                self.main.push_back(key.clone());
-                self.cache.insert(
-                    key,
-                    CacheEntry {
-                        value,
-                        freq: AtomicU8::new(1),
-                        location: QueueLocation::Main,
-                    },
-                );
-                return None;
-            }
+        let fingerprint = self.fingerprint(&key);
+        let ghost_hit = self.ghost_set.remove(&fingerprint);
+        if ghost_hit {
+            self.ghost.retain(|f| *f != fingerprint);
         }
 
-        if self.small.len() >= self.small_capacity {
-            self.evict_from_small();
+        // A hit in the ghost queue goes straight to main, as do values heavier than the whole
+        // small queue, which would otherwise be evicted by their own insertion
+        if ghost_hit || weight > self.small_budget {
+            self.main_bytes += weight;
+            self.main.push_back(key.clone());
+            self.cache.insert(
+                key,
+                CacheEntry {
+                    value,
+                    weight,
+                    freq: AtomicU8::new(1),
+                    location: QueueLocation::Main,
+                },
+            );
+            self.enforce_main_budget();
+            return None;
         }
 
+        self.small_bytes += weight;
         self.small.push_back(key.clone());
         self.cache.insert(
             key,
             CacheEntry {
                 value,
+                weight,
                 freq: AtomicU8::new(0),
                 location: QueueLocation::Small,
             },
         );
+        self.enforce_small_budget();
 
         None
     }
 
     fn contains(&self, key: &K) -> bool {
-        self.cache
-            .get(key)
-            .map(|entry| entry.location != QueueLocation::Ghost)
-            .unwrap_or(false)
+        self.cache.contains_key(key)
     }
 
     fn len(&self) -> usize {
@@ -253,5 +339,168 @@ where
         self.small.clear();
         self.main.clear();
         self.ghost.clear();
+        self.ghost_set.clear();
+        self.small_bytes = 0;
+        self.main_bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+
+    // 100 bytes of small budget, 900 of main
+    fn cache() -> S3Fifo<&'static str, Vec<u8>> {
+        S3Fifo::new(1000)
+    }
+
+    fn bytes(cache: &S3Fifo<&'static str, Vec<u8>>) -> (usize, usize) {
+        (cache.small_bytes, cache.main_bytes)
+    }
+
+    #[test]
+    fn new_entries_are_admitted_to_small() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 40]);
+        cache.insert("b", vec![0; 50]);
+
+        assert_eq!(bytes(&cache), (90, 0));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn entries_read_twice_are_promoted_to_main() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 60]);
+        cache.get(&"a");
+        cache.get(&"a");
+        cache.insert("b", vec![0; 60]);
+
+        assert_eq!(bytes(&cache), (60, 60));
+        assert_eq!(cache.cache[&"a"].location, QueueLocation::Main);
+        assert!(cache.contains(&"b"));
+    }
+
+    #[test]
+    fn unread_entries_are_evicted_from_small() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 60]);
+        cache.insert("b", vec![0; 60]);
+
+        assert_eq!(bytes(&cache), (60, 0));
+        assert!(!cache.contains(&"a"));
+        assert!(cache.contains(&"b"));
+    }
+
+    #[test]
+    fn evicted_entries_are_readmitted_to_main_from_ghost() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 60]);
+        cache.insert("b", vec![0; 60]);
+        cache.insert("a", vec![0; 60]);
+
+        assert_eq!(bytes(&cache), (60, 60));
+        assert_eq!(cache.cache[&"a"].location, QueueLocation::Main);
+        assert!(cache.ghost.is_empty());
+    }
+
+    #[test]
+    fn replacing_updates_the_weight_in_place() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 40]);
+
+        assert_eq!(
+            cache.insert("a", vec![0; 70]).map(|old| old.len()),
+            Some(40)
+        );
+        assert_eq!(bytes(&cache), (70, 0));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn replacing_past_the_small_budget_moves_to_main() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 40]);
+        cache.insert("a", vec![0; 300]);
+
+        assert_eq!(bytes(&cache), (0, 300));
+        assert!(cache.small.is_empty());
+        assert_eq!(cache.main, ["a"]);
+    }
+
+    #[test]
+    fn values_heavier_than_small_go_to_main() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 40]);
+        cache.insert("big", vec![0; 500]);
+
+        assert_eq!(bytes(&cache), (40, 500));
+        assert!(cache.contains(&"a"));
+        assert!(cache.contains(&"big"));
+    }
+
+    #[test]
+    fn values_heavier_than_main_are_not_cached() {
+        let mut cache = cache();
+        cache.insert("huge", vec![0; 901]);
+
+        assert_eq!(bytes(&cache), (0, 0));
+        assert!(!cache.contains(&"huge"));
+    }
+
+    #[test]
+    fn replacing_with_a_value_heavier_than_main_drops_the_old_one() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 40]);
+        cache.insert("b", vec![0; 500]);
+
+        assert_eq!(
+            cache.insert("a", vec![0; 901]).map(|old| old.len()),
+            Some(40)
+        );
+        assert_eq!(
+            cache.insert("b", vec![0; 901]).map(|old| old.len()),
+            Some(500)
+        );
+        assert_eq!(bytes(&cache), (0, 0));
+        assert!(!cache.contains(&"a") && !cache.contains(&"b"));
+        assert!(cache.small.is_empty() && cache.main.is_empty());
+    }
+
+    #[test]
+    fn main_evicts_down_to_its_budget() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 500]);
+        cache.insert("b", vec![0; 500]);
+
+        assert_eq!(bytes(&cache), (0, 500));
+        assert!(!cache.contains(&"a"));
+    }
+
+    #[test]
+    fn max_weight_is_the_main_budget() {
+        let mut cache = cache();
+        assert_eq!(cache.max_weight(), 900);
+
+        cache.insert("fits", vec![0; 900]);
+        assert!(cache.contains(&"fits"));
+    }
+
+    #[test]
+    fn clear_resets_the_accounting() {
+        let mut cache = cache();
+        cache.insert("a", vec![0; 60]);
+        cache.insert("b", vec![0; 60]);
+        cache.insert("big", vec![0; 500]);
+        cache.clear();
+
+        assert_eq!(bytes(&cache), (0, 0));
+        assert_eq!(cache.len(), 0);
+        assert!(cache.ghost.is_empty() && cache.ghost_set.is_empty());
+
+        // Nothing is remembered as a ghost, so this is a fresh admission to small
+        cache.insert("a", vec![0; 60]);
+        assert_eq!(bytes(&cache), (60, 0));
     }
 }
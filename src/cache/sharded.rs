@@ -0,0 +1,115 @@
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::sync::RwLock;
+
+use crate::cache::Cache;
+use crate::cache::Weighted;
+use crate::cache::s3fifo::{CacheStats, S3Fifo};
+
+/// S3-FIFO split into independent shards selected by key hash.
+///
+/// Each shard has its own lock and an equal share of the byte budget, so misses on different
+/// images only contend when they land on the same shard. Hits take a shard read lock.
+pub struct ShardedS3Fifo<K, V> {
+    shards: Box<[RwLock<S3Fifo<K, V>>]>,
+    hasher: RandomState,
+}
+
+impl<K, V> ShardedS3Fifo<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Weighted + Clone,
+{
+    /// Create `shards` shards sharing `max_bytes` evenly
+    pub fn new(max_bytes: usize, shards: usize) -> Self {
+        let shards = shards.max(1);
+        let shard_bytes = max_bytes / shards;
+
+        Self {
+            shards: (0..shards)
+                .map(|_| RwLock::new(S3Fifo::new(shard_bytes)))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn shard(&self, key: &K) -> &RwLock<S3Fifo<K, V>> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let shard = self.shard(key).read().unwrap_or_else(|e| e.into_inner());
+        shard.get(key).cloned()
+    }
+
+    /// Like [`Self::get`], but without counting a hit or miss or raising the entry's frequency
+    pub fn peek(&self, key: &K) -> Option<V> {
+        let shard = self.shard(key).read().unwrap_or_else(|e| e.into_inner());
+        shard.peek(key).cloned()
+    }
+
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        let mut shard = self.shard(&key).write().unwrap_or_else(|e| e.into_inner());
+        shard.insert(key, value)
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        let shard = self.shard(key).read().unwrap_or_else(|e| e.into_inner());
+        shard.contains(key)
+    }
+
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.write().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+
+    /// Weight of the largest value a shard admits, anything heavier is never cached
+    pub fn max_weight(&self) -> usize {
+        self.shards[0]
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .max_weight()
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Statistics summed over all shards
+    pub fn stats(&self) -> CacheStats {
+        let mut total = CacheStats {
+            hits: 0,
+            misses: 0,
+            hit_rate: 0.0,
+            size: 0,
+            bytes: 0,
+            max_bytes: 0,
+        };
+
+        for shard in &self.shards {
+            let stats = shard.read().unwrap_or_else(|e| e.into_inner()).stats();
+            total.hits += stats.hits;
+            total.misses += stats.misses;
+            total.size += stats.size;
+            total.bytes += stats.bytes;
+            total.max_bytes += stats.max_bytes;
+        }
+
+        let lookups = total.hits + total.misses;
+        if lookups > 0 {
+            total.hit_rate = (total.hits as f64 / lookups as f64) * 100.0;
+        }
+
+        total
+    }
+
+    pub fn reset_stats(&self) {
+        for shard in &self.shards {
+            shard
+                .read()
+                .unwrap_or_else(|e| e.into_inner())
+                .reset_stats();
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::OnceCell;
+
+/// Coalesces concurrent loads of the same key so only one of them runs.
+///
+/// Callers that arrive while a load is in flight wait for it and receive a clone of its result.
+/// If the caller running the load is cancelled, one of the waiters takes over.
+pub struct SingleFlight<K, V> {
+    inflight: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+    coalesced: AtomicU64,
+}
+
+impl<K, V> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self {
+            inflight: Mutex::new(HashMap::new()),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K, V> SingleFlight<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `load` for `key` unless a load for it is already in flight, in which case wait for
+    /// that one instead
+    pub async fn run<F, Fut>(&self, key: K, load: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let cell = {
+            let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+            inflight.entry(key.clone()).or_default().clone()
+        };
+
+        let mut loaded = false;
+        let value = cell
+            .get_or_init(|| {
+                loaded = true;
+                load()
+            })
+            .await
+            .clone();
+
+        if loaded {
+            let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+            if inflight
+                .get(&key)
+                .is_some_and(|current| Arc::ptr_eq(current, &cell))
+            {
+                inflight.remove(&key);
+            }
+        } else {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+        }
+
+        value
+    }
+
+    /// Number of callers that received the result of another caller's load
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use bytes::Bytes;
+    use futures_util::FutureExt;
+    use futures_util::future::join_all;
+    use tokio::sync::Notify;
+
+    use super::*;
+
+    const CALLERS: usize = 16;
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_load() {
+        let flight = SingleFlight::<&str, Bytes>::new();
+        let loads = AtomicUsize::new(0);
+        let release = Notify::new();
+
+        let calls = join_all((0..CALLERS).map(|_| {
+            flight.run("a.png", || async {
+                loads.fetch_add(1, Ordering::Relaxed);
+                release.notified().await;
+                Bytes::from(vec![1, 2, 3])
+            })
+        }));
+        let mut calls = std::pin::pin!(calls);
+
+        // One poll has every caller join the flight before the load can finish
+        assert!(calls.as_mut().now_or_never().is_none());
+        release.notify_one();
+        let results = calls.await;
+
+        assert_eq!(loads.load(Ordering::Relaxed), 1);
+        assert!(
+            results
+                .iter()
+                .all(|bytes| bytes.as_ptr() == results[0].as_ptr())
+        );
+        assert_eq!(results[0], [1, 2, 3][..]);
+        assert_eq!(flight.coalesced(), CALLERS as u64 - 1);
+        assert!(flight.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn waiter_takes_over_from_a_cancelled_leader() {
+        let flight = SingleFlight::<&str, Bytes>::new();
+
+        let mut leader = Box::pin(flight.run("a.png", std::future::pending));
+        assert!(leader.as_mut().now_or_never().is_none());
+
+        let mut waiter = Box::pin(flight.run("a.png", || async { Bytes::from_static(b"waiter") }));
+        assert!(waiter.as_mut().now_or_never().is_none());
+
+        drop(leader);
+        assert_eq!(waiter.await, "waiter");
+        assert_eq!(flight.coalesced(), 0);
+        assert!(flight.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn caller_after_a_timed_out_leader_loads_again() {
+        let flight = SingleFlight::<&str, Bytes>::new();
+
+        let leader = flight.run("a.png", std::future::pending);
+        let timed_out = tokio::time::timeout(std::time::Duration::from_millis(10), leader).await;
+        assert!(timed_out.is_err());
+
+        let later = flight.run("a.png", || async { Bytes::from_static(b"later") });
+        assert_eq!(later.await, "later");
+        assert_eq!(flight.coalesced(), 0);
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::{HeaderMap, header};
+
+/// Evaluate `If-None-Match` and `If-Modified-Since` against the current validators.
+///
+/// Returns true if the client's copy is current and a `304 Not Modified` should be sent.
+/// As required by RFC 9110, `If-Modified-Since` is ignored when `If-None-Match` is present.
+pub fn is_not_modified(headers: &HeaderMap, etag: &str, last_modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        return if_none_match
+            .to_str()
+            .map(|value| etag_matches(value, etag))
+            .unwrap_or(false);
+    }
+
+    let (Some(if_modified_since), Some(last_modified)) =
+        (headers.get(header::IF_MODIFIED_SINCE), last_modified)
+    else {
+        return false;
+    };
+
+    if_modified_since
+        .to_str()
+        .ok()
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .is_some_and(|since| truncate_to_seconds(last_modified) <= since)
+}
+
+// If-None-Match uses the weak comparison, so `W/` prefixes are ignored on both sides
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+// HTTP dates only carry whole seconds
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since_epoch) => UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs()),
+        Err(_) => time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    const ETAG: &str = "\"0123456789abcdef\"";
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn modified() -> SystemTime {
+        // Sun, 06 Nov 1994 08:49:37 GMT, plus a fraction the header cannot carry
+        UNIX_EPOCH + Duration::from_millis(784_111_777_500)
+    }
+
+    fn not_modified(pairs: &[(header::HeaderName, &str)]) -> bool {
+        is_not_modified(&headers(pairs), ETAG, Some(modified()))
+    }
+
+    #[test]
+    fn without_conditional_headers() {
+        assert!(!not_modified(&[]));
+    }
+
+    #[test]
+    fn if_none_match_wildcard() {
+        assert!(not_modified(&[(header::IF_NONE_MATCH, "*")]));
+    }
+
+    #[test]
+    fn if_none_match_lists() {
+        assert!(not_modified(&[(header::IF_NONE_MATCH, ETAG)]));
+        assert!(not_modified(&[(
+            header::IF_NONE_MATCH,
+            "\"other\", \"0123456789abcdef\",\"last\""
+        )]));
+        assert!(!not_modified(&[(
+            header::IF_NONE_MATCH,
+            "\"other\", \"last\""
+        )]));
+        assert!(!not_modified(&[(
+            header::IF_NONE_MATCH,
+            "0123456789abcdef"
+        )]));
+    }
+
+    #[test]
+    fn if_none_match_uses_weak_comparison() {
+        assert!(not_modified(&[(
+            header::IF_NONE_MATCH,
+            "W/\"0123456789abcdef\""
+        )]));
+        assert!(is_not_modified(
+            &headers(&[(header::IF_NONE_MATCH, ETAG)]),
+            "W/\"0123456789abcdef\"",
+            None
+        ));
+        assert!(!not_modified(&[(header::IF_NONE_MATCH, "W/\"other\"")]));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let current_date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert!(!not_modified(&[
+            (header::IF_NONE_MATCH, "\"other\""),
+            (header::IF_MODIFIED_SINCE, current_date),
+        ]));
+        assert!(not_modified(&[
+            (header::IF_NONE_MATCH, ETAG),
+            (header::IF_MODIFIED_SINCE, "Sat, 01 Jan 1994 00:00:00 GMT"),
+        ]));
+    }
+
+    #[test]
+    fn if_modified_since_at_one_second_resolution() {
+        assert!(not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        )]));
+        assert!(not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 06 Nov 1994 08:49:38 GMT"
+        )]));
+        assert!(!not_modified(&[(
+            header::IF_MODIFIED_SINCE,
+            "Sun, 06 Nov 1994 08:49:36 GMT"
+        )]));
+    }
+
+    #[test]
+    fn if_modified_since_needs_a_modification_time() {
+        let headers = headers(&[(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert!(!is_not_modified(&headers, ETAG, None));
+    }
+
+    #[test]
+    fn malformed_dates_are_ignored() {
+        for date in [
+            "yesterday",
+            "",
+            "Sun, 06 Nov 1994 08:49",
+            "1994-11-06T08:49:37Z",
+        ] {
+            assert!(
+                !not_modified(&[(header::IF_MODIFIED_SINCE, date)]),
+                "{date:?}"
+            );
+        }
+    }
+}
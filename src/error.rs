@@ -1,3 +1,6 @@
+#[cfg(feature = "cache")]
+use std::sync::Arc;
+
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -19,6 +22,26 @@ pub enum ImageServerError {
     #[error("cache error: {0}")]
     CacheError(String),
 
+    /// Error of a coalesced load, shared by every request that waited on it
+    #[cfg(feature = "cache")]
+    #[error(transparent)]
+    Shared(Arc<ImageServerError>),
+
+    /// Query string requested an unknown or malformed image operation
+    #[cfg(feature = "processing")]
+    #[error("invalid image operation: {0}")]
+    InvalidOperation(String),
+
+    /// Image could not be decoded, processed or re-encoded
+    #[cfg(feature = "processing")]
+    #[error("image processing error: {0}")]
+    ProcessingError(String),
+
+    /// WebAssembly plugin failed to load or run
+    #[cfg(feature = "wasm")]
+    #[error("plugin error: {0}")]
+    PluginError(String),
+
     /// TLS configuration error
     #[error("TLS configuration error: {0}")]
     TlsError(String),
@@ -40,6 +63,16 @@ impl ImageServerError {
 
             #[cfg(feature = "cache")]
             ImageServerError::CacheError(_) => 500,
+            #[cfg(feature = "cache")]
+            ImageServerError::Shared(inner) => inner.status_code(),
+
+            #[cfg(feature = "processing")]
+            ImageServerError::InvalidOperation(_) => 400,
+            #[cfg(feature = "processing")]
+            ImageServerError::ProcessingError(_) => 500,
+
+            #[cfg(feature = "wasm")]
+            ImageServerError::PluginError(_) => 500,
 
             ImageServerError::IoError(_) => 500,
             ImageServerError::TlsError(_) => 500,
@@ -52,6 +85,12 @@ impl ImageServerError {
             ImageServerError::NotFound { .. } => "Image not found".to_string(),
             ImageServerError::InvalidFormat => "Invalid or unsupported image format".to_string(),
 
+            #[cfg(feature = "processing")]
+            ImageServerError::InvalidOperation(msg) => format!("Invalid image operation: {msg}"),
+
+            #[cfg(feature = "cache")]
+            ImageServerError::Shared(inner) => inner.user_message(),
+
             _ => "Internal server error".to_string(),
         }
     }
@@ -1,70 +1,134 @@
 use axum::extract::Path;
 
-#[cfg(feature = "cache")]
+#[cfg(feature = "processing")]
+use axum::extract::Query;
+
 use axum::extract::State;
 
-use axum::http::{StatusCode, header};
-use axum::response::{IntoResponse, Response};
+use axum::http::HeaderMap;
+use axum::response::Response;
 
 use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+#[cfg(any(feature = "cache", feature = "processing"))]
+use std::sync::Arc;
 
-#[cfg(feature = "cache")]
 use crate::AppState;
 #[cfg(feature = "cache")]
-use crate::cache::Cache;
+use crate::CacheKey;
+#[cfg(feature = "processing")]
+use crate::body::ImageBody;
+use crate::body::{FileHashes, ImageFile, ImageResponse};
 use crate::error::{ImageServerError, Result};
-
+#[cfg(feature = "processing")]
+use crate::plugin::{OperationParams, registry::PluginRegistry};
+#[cfg(feature = "processing")]
+use crate::processing::{consumed_params, has_operations, process_image};
+use crate::response::{error_response, image_response};
+use crate::roots::ImageRoots;
+use crate::sniff::{ImageType, SNIFF_LEN};
 
 // Core image processing logic with caching
 #[cfg(feature = "cache")]
 pub async fn handle_image_request_cached(
     state: AppState,
     image: String,
-) -> Result<(String, Vec<u8>)> {
-    let cache_key = image.clone();
-
-    {
-        let cache = state.cache.read().await;
-        if let Some(cached_bytes) = cache.get(&cache_key) {
-            let extension = std::path::Path::new(&image)
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .map(|s| s.to_lowercase())
-                .unwrap_or_default();
-
-            let img_type = match extension.as_str() {
-                "png" => "image/png",
-                "jpg" | "jpeg" => "image/jpeg",
-                "webp" => "image/webp",
-                "gif" => "image/gif",
-                "svg" => "image/svg+xml",
-                _ => "application/octet-stream",
-            };
-
-            return Ok((img_type.to_string(), cached_bytes.clone()));
-        }
+    #[cfg(feature = "processing")] params: OperationParams,
+) -> Result<ImageResponse> {
+    #[cfg(feature = "processing")]
+    let cache_key = cache_key(&state.plugins, &image, &params);
+    #[cfg(not(feature = "processing"))]
+    let cache_key = (image.clone(), String::new());
+
+    if let Some(cached) = state.cache.get(&cache_key) {
+        return Ok(ImageResponse::Buffered(cached));
+    }
+
+    // Concurrent misses on the same key wait for a single load instead of each resolving,
+    // reading and processing the file
+    state
+        .inflight
+        .run(cache_key.clone(), || async {
+            #[cfg(feature = "processing")]
+            let result = load_image(&state, image, cache_key, params).await;
+            #[cfg(not(feature = "processing"))]
+            let result = load_image(&state, image, cache_key).await;
+
+            result.map_err(Arc::new)
+        })
+        .await
+        .map_err(ImageServerError::Shared)
+}
+
+// The load behind a cache miss, run once per flight
+#[cfg(feature = "cache")]
+async fn load_image(
+    state: &AppState,
+    image: String,
+    cache_key: CacheKey,
+    #[cfg(feature = "processing")] params: OperationParams,
+) -> Result<ImageResponse> {
+    // A flight that finished between the lookup and this one starting has filled the cache
+    if let Some(cached) = state.cache.peek(&cache_key) {
+        return Ok(ImageResponse::Buffered(cached));
     }
 
-    let (content_type, bytes) = handle_image_request(image.clone()).await?;
+    let file = handle_image_request(&state.roots, &state.file_hashes, image).await?;
+
+    #[cfg(feature = "processing")]
+    let processed = has_operations(&state.plugins, &params);
+    #[cfg(not(feature = "processing"))]
+    let processed = false;
 
-    {
-        let mut cache = state.cache.write().await;
-        cache.insert(cache_key, bytes.clone());
+    // Large originals are streamed from disk instead of pushing everything else out of the cache
+    if !processed && file.len > state.cache_max_file_size {
+        return Ok(ImageResponse::Streamed(file));
     }
 
-    Ok((content_type, bytes))
+    let result = file.read().await;
+
+    #[cfg(feature = "processing")]
+    let result = handle_processing(state.plugins.clone(), result, params).await;
+
+    let body = result?;
+    state.cache.insert(cache_key, body.clone());
+
+    Ok(ImageResponse::Buffered(body))
 }
 
-pub async fn handle_image_request(image: String) -> Result<(String, Vec<u8>)> {
-    let base_dir = fs::canonicalize("./images/")
-        .await
-        .map_err(|e| ImageServerError::Internal(format!("Base dir config error: {}", e)))?;
+/// Untouched originals are cached under their path alone, processed variants together with the
+/// canonical parameters so reordered query strings share an entry. `params` must already be
+/// reduced to [`consumed_params`]
+#[cfg(all(feature = "cache", feature = "processing"))]
+pub(crate) fn cache_key(
+    plugins: &PluginRegistry,
+    image: &str,
+    params: &OperationParams,
+) -> CacheKey {
+    if has_operations(plugins, params) {
+        (image.to_string(), params.canonical())
+    } else {
+        (image.to_string(), String::new())
+    }
+}
 
+/// Resolve `image`, a relative path such as `products/2024/sku123.jpg`, inside the root or the
+/// mount it names and read its metadata.
+///
+/// The file itself is only read once it is known whether it will be cached, processed or
+/// streamed, apart from hashing it for the `ETag` the first time this version of it is served.
+pub async fn handle_image_request(
+    roots: &ImageRoots,
+    file_hashes: &FileHashes,
+    image: String,
+) -> Result<ImageFile> {
     if image.contains("..") || image.starts_with('/') || image.contains('\\') {
         return Err(ImageServerError::InvalidFormat);
     }
 
-    let image_path = base_dir.join(&image);
+    let (base_dir, relative) = roots.resolve(&image);
+    let image_path = base_dir.join(relative);
 
     let canonical_path =
         fs::canonicalize(&image_path)
@@ -73,77 +137,121 @@ pub async fn handle_image_request(image: String) -> Result<(String, Vec<u8>)> {
                 path: image.clone(),
             })?;
 
-    if !canonical_path.starts_with(&base_dir) {
+    if !canonical_path.starts_with(base_dir) {
         return Err(ImageServerError::InvalidFormat);
     }
 
-    let extension = canonical_path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .ok_or(ImageServerError::InvalidFormat)?
-        .to_lowercase();
+    let metadata = fs::metadata(&canonical_path).await?;
 
-    let img_type = match extension.as_str() {
-        "png" => "image/png",
-        "jpg" | "jpeg" => "image/jpeg",
-        "webp" => "image/webp",
-        "gif" => "image/gif",
-        "svg" => "image/svg+xml",
-        _ => return Err(ImageServerError::InvalidFormat),
-    };
-
-    let bytes = fs::read(&canonical_path).await?;
+    // Directories are reachable through the wildcard route but never served
+    if !metadata.is_file() {
+        return Err(ImageServerError::NotFound { path: image });
+    }
 
-    Ok((img_type.to_string(), bytes))
+    // The type comes from the file's signature, so a mislabelled extension can't change how the
+    // content is served
+    let img_type = sniff_file(&canonical_path).await?;
+
+    let last_modified = metadata.modified().ok();
+    let etag = file_hashes
+        .etag(&canonical_path, metadata.len(), last_modified)
+        .await?;
+
+    Ok(ImageFile {
+        path: canonical_path,
+        content_type: img_type.mime_type().to_string(),
+        len: metadata.len(),
+        last_modified,
+        etag,
+    })
 }
 
+async fn sniff_file(path: &std::path::Path) -> Result<ImageType> {
+    let file = fs::File::open(path).await?;
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    file.take(SNIFF_LEN as u64).read_to_end(&mut head).await?;
 
-#[cfg(not(feature = "cache"))]
-pub async fn handler(Path(image): Path<String>) -> Response {
-    let result = handle_image_request(image).await;
+    ImageType::sniff(&head).ok_or(ImageServerError::InvalidFormat)
+}
 
-    match result {
-        Ok((content_type, body)) => {
-            (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], body).into_response()
-        }
-        Err(err) => {
-            let status_code = StatusCode::from_u16(err.status_code())
-                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
-            let message = err.user_message();
+// Runs the requested operations off the async runtime since decoding and encoding are CPU bound
+#[cfg(feature = "processing")]
+pub async fn handle_processing(
+    plugins: Arc<PluginRegistry>,
+    result: Result<ImageBody>,
+    params: OperationParams,
+) -> Result<ImageBody> {
+    let source = result?;
+
+    if !has_operations(&plugins, &params) {
+        return Ok(source);
+    }
 
-            eprintln!("Error serving image: {}", err);
+    let (content_type, bytes) =
+        tokio::task::spawn_blocking(move || process_image(&source.bytes, &params, &plugins))
+            .await
+            .map_err(|e| ImageServerError::Internal(format!("processing task failed: {}", e)))??;
 
-            (status_code, message).into_response()
+    // The variant changes whenever its source file does
+    Ok(ImageBody::new(content_type, bytes, source.last_modified))
+}
+
+#[cfg(not(feature = "cache"))]
+pub async fn handler(
+    State(state): State<AppState>,
+    Path(image): Path<String>,
+    #[cfg(feature = "processing")] Query(params): Query<OperationParams>,
+    headers: HeaderMap,
+) -> Response {
+    #[cfg(feature = "processing")]
+    let params = consumed_params(&state.plugins, params);
+
+    let result = handle_image_request(&state.roots, &state.file_hashes, image).await;
+
+    // Only processed images are read into memory, originals are streamed from disk
+    #[cfg(feature = "processing")]
+    let result = match result {
+        Ok(file) if has_operations(&state.plugins, &params) => {
+            handle_processing(state.plugins, file.read().await, params)
+                .await
+                .map(ImageResponse::Buffered)
         }
+        other => other.map(ImageResponse::Streamed),
+    };
+    #[cfg(not(feature = "processing"))]
+    let result = result.map(ImageResponse::Streamed);
+
+    match result {
+        Ok(body) => image_response(&headers, body).await,
+        Err(err) => error_response(err),
     }
 }
 
 #[cfg(feature = "cache")]
-pub async fn handler(State(state): State<AppState>, Path(image): Path<String>) -> Response {
+pub async fn handler(
+    State(state): State<AppState>,
+    Path(image): Path<String>,
+    #[cfg(feature = "processing")] Query(params): Query<OperationParams>,
+    headers: HeaderMap,
+) -> Response {
+    #[cfg(feature = "processing")]
+    let params = consumed_params(&state.plugins, params);
+
+    #[cfg(feature = "processing")]
+    let result = handle_image_request_cached(state, image, params).await;
+    #[cfg(not(feature = "processing"))]
     let result = handle_image_request_cached(state, image).await;
 
-
     match result {
-        Ok((content_type, body)) => {
-            (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], body).into_response()
-        }
-        Err(err) => {
-            let status_code = StatusCode::from_u16(err.status_code())
-                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
-            let message = err.user_message();
-
-            eprintln!("Error serving image: {}", err);
-
-            (status_code, message).into_response()
-        }
+        Ok(body) => image_response(&headers, body).await,
+        Err(err) => error_response(err),
     }
 }
 
 /// Stats endpoint to monitor cache performance
 #[cfg(feature = "cache")]
 pub async fn stats_handler(State(state): State<AppState>) -> String {
-    let cache = state.cache.read().await;
-    let stats = cache.stats();
+    let stats = state.cache.stats();
 
     format!(
         "Cache Statistics\n\
@@ -151,8 +259,76 @@ pub async fn stats_handler(State(state): State<AppState>) -> String {
          Hits: {}\n\
          Misses: {}\n\
          Hit Rate: {:.2}%\n\
-         Current Size: {}/{}\n",
-        stats.hits, stats.misses, stats.hit_rate, stats.size, stats.capacity
+         Entries: {}\n\
+         Bytes Used: {}/{}\n\
+         Shards: {}\n\
+         Coalesced: {}\n",
+        stats.hits,
+        stats.misses,
+        stats.hit_rate,
+        stats.size,
+        stats.bytes,
+        stats.max_bytes,
+        state.cache.shard_count(),
+        state.inflight.coalesced()
     )
 }
 
+#[cfg(all(test, feature = "cache", feature = "processing"))]
+mod tests {
+    use super::*;
+    use crate::processing::ProcessingLimits;
+
+    fn key(image: &str, query: &str) -> CacheKey {
+        let plugins = PluginRegistry::with_inbuilt(ProcessingLimits::default());
+        let pairs = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (key.to_string(), value.to_string())
+            })
+            .collect::<Vec<_>>();
+
+        let params = consumed_params(&plugins, OperationParams::from(pairs));
+        cache_key(&plugins, image, &params)
+    }
+
+    #[test]
+    fn reordered_queries_share_a_key() {
+        assert_eq!(
+            key("a.png", "w=10&h=20&to=webp"),
+            key("a.png", "to=webp&h=20&w=10")
+        );
+    }
+
+    #[test]
+    fn different_values_get_different_keys() {
+        assert_ne!(key("a.png", "w=10"), key("a.png", "w=20"));
+        assert_ne!(key("a.png", "w=10"), key("a.png", "w=10&resfilter=nearest"));
+        assert_ne!(key("a.png", "w=10"), key("b.png", "w=10"));
+    }
+
+    #[test]
+    fn unread_keys_do_not_change_the_key() {
+        assert_eq!(key("a.png", "w=10&cb=1"), key("a.png", "w=10"));
+        assert_eq!(key("a.png", "cb=1"), key("a.png", ""));
+        // `resfilter` only matters to resize
+        assert_eq!(
+            key("a.png", "blur=2&resfilter=nearest"),
+            key("a.png", "blur=2")
+        );
+    }
+
+    #[test]
+    fn originals_are_keyed_by_path_alone() {
+        assert_eq!(key("a.png", ""), ("a.png".to_string(), String::new()));
+    }
+
+    #[test]
+    fn paths_cannot_pass_for_parameters() {
+        // A file literally named `a.png?w=10`, after percent-decoding
+        assert_ne!(key("a.png?w=10", ""), key("a.png", "w=10"));
+        assert_ne!(key("a.png\0w=10", ""), key("a.png", "w=10"));
+    }
+}
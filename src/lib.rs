@@ -1,11 +1,15 @@
-#[cfg(feature = "cache")]
 use std::sync::Arc;
 
+use crate::body::FileHashes;
 #[cfg(feature = "cache")]
-use tokio::sync::RwLock;
-
+use crate::body::{ImageBody, ImageResponse};
+#[cfg(feature = "cache")]
+use crate::cache::{sharded::ShardedS3Fifo, single_flight::SingleFlight};
 #[cfg(feature = "cache")]
-use crate::cache::s3fifo::S3Fifo;
+use crate::error::ImageServerError;
+#[cfg(feature = "processing")]
+use crate::plugin::registry::PluginRegistry;
+use crate::roots::ImageRoots;
 
 pub mod error;
 
@@ -17,13 +21,45 @@ pub mod args;
 pub mod server;
 
 pub mod handler;
-// pub mod plugin;
 
-pub const ADDR: [u8; 4] = [127, 0, 0, 1];
+pub mod body;
+
+pub mod conditional;
+
+pub mod range;
+
+pub mod response;
 
+pub mod roots;
 
+pub mod sniff;
+
+#[cfg(feature = "processing")]
+pub mod processing;
+
+#[cfg(feature = "processing")]
+pub mod plugin;
+
+/// Image path and the canonical parameters of the variant, empty for the original. Kept apart so
+/// no decoded path can pass for another path plus parameters
 #[cfg(feature = "cache")]
+pub type CacheKey = (String, String);
+
+/// Cache misses currently being loaded, keyed like the cache
+#[cfg(feature = "cache")]
+pub type InflightLoads = SingleFlight<CacheKey, Result<ImageResponse, Arc<ImageServerError>>>;
+
 #[derive(Clone)]
 pub struct AppState {
-    pub cache: Arc<RwLock<S3Fifo<String, Vec<u8>>>>,
-}
\ No newline at end of file
+    pub roots: Arc<ImageRoots>,
+    pub file_hashes: Arc<FileHashes>,
+    #[cfg(feature = "cache")]
+    pub cache: Arc<ShardedS3Fifo<CacheKey, ImageBody>>,
+    #[cfg(feature = "cache")]
+    pub inflight: Arc<InflightLoads>,
+    /// Unprocessed files larger than this are streamed and never cached
+    #[cfg(feature = "cache")]
+    pub cache_max_file_size: u64,
+    #[cfg(feature = "processing")]
+    pub plugins: Arc<PluginRegistry>,
+}
@@ -1,48 +1,112 @@
 use axum::Router;
 
-#[cfg(feature = "cache")]
-use axum::extract::State;
-
 use axum::routing::get;
 
-#[cfg(feature = "cache")]
 use nano_image_server::AppState;
 use nano_image_server::args::Args;
+use nano_image_server::body::FileHashes;
 
-#[cfg(not(feature="cache"))]
 use nano_image_server::handler::handler;
+use nano_image_server::roots::ImageRoots;
 #[cfg(not(feature = "tls"))]
 use nano_image_server::server::http::serve_http;
 #[cfg(feature = "tls")]
 use nano_image_server::server::https::serve_https;
+#[cfg(feature = "tls")]
+use nano_image_server::server::redirect::{RedirectConfig, base_authority};
+#[cfg(feature = "tls")]
+use nano_image_server::server::tls::TlsOptions;
+#[cfg(all(unix, not(feature = "tls")))]
+use nano_image_server::server::unix::UnixSocketConfig;
 
 #[cfg(feature = "cache")]
-use nano_image_server::cache::s3fifo::S3Fifo;
+use nano_image_server::cache::{sharded::ShardedS3Fifo, single_flight::SingleFlight};
+#[cfg(feature = "wasm")]
+use nano_image_server::plugin::external::{PluginLimits, load_plugins};
+#[cfg(feature = "processing")]
+use nano_image_server::plugin::registry::PluginRegistry;
 
-#[cfg(feature = "cache")]
 use std::sync::Arc;
 
-#[cfg(feature = "cache")]
-use tokio::sync::RwLock;
+#[cfg(feature = "tls")]
+use std::net::SocketAddr;
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
 
+    let roots = match ImageRoots::new(&args.root, &args.mounts) {
+        Ok(roots) => Arc::new(roots),
+        Err(err) => {
+            eprintln!("Unable to serve images: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    #[cfg(feature = "processing")]
+    #[allow(unused_mut)]
+    let mut plugins = PluginRegistry::with_inbuilt(args.processing_limits);
+
+    #[cfg(feature = "wasm")]
+    if let Some(plugin_dir) = &args.plugin_dir {
+        let limits = PluginLimits {
+            fuel: args.plugin_fuel,
+            max_memory: args.plugin_memory,
+        };
+
+        match load_plugins(plugin_dir, limits, &mut plugins) {
+            Ok(count) => println!("Loaded {} plugin(s) from {}", count, plugin_dir.display()),
+            Err(err) => {
+                eprintln!(
+                    "Unable to load plugins from {}: {}",
+                    plugin_dir.display(),
+                    err
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    #[cfg(feature = "cache")]
+    let cache = ShardedS3Fifo::new(args.cache_max_bytes, args.cache_shards);
+
+    // Originals below the threshold are read whole to be cached, which is wasted on any the
+    // shards are too small to hold
+    #[cfg(feature = "cache")]
+    let cache_max_file_size = {
+        let max_weight = cache.max_weight() as u64;
+        if args.cache_max_file_size > max_weight {
+            eprintln!(
+                "WARNING: --cache-max-file-size {} exceeds the {} bytes a cache shard can hold, using {}",
+                args.cache_max_file_size, max_weight, max_weight
+            );
+        }
+        args.cache_max_file_size.min(max_weight)
+    };
+
+    let state = AppState {
+        roots: roots.clone(),
+        file_hashes: Arc::new(FileHashes::new()),
+        #[cfg(feature = "cache")]
+        cache: Arc::new(cache),
+        #[cfg(feature = "cache")]
+        inflight: Arc::new(SingleFlight::new()),
+        #[cfg(feature = "cache")]
+        cache_max_file_size,
+        #[cfg(feature = "processing")]
+        plugins: Arc::new(plugins),
+    };
+
+    let app = Router::new().route("/{*path}", get(handler));
+
     #[cfg(feature = "cache")]
     let app = {
-        use nano_image_server::{AppState, handler::{handler, stats_handler}};
-
-        let cache = Arc::new(RwLock::new(S3Fifo::new(args.cache_capacity)));
-        let state = AppState { cache };
-        Router::new()
-            .route("/{image}", get(handler))
-            .route("/_stats", get(stats_handler))
-            .with_state(state)
+        use nano_image_server::handler::stats_handler;
+
+        app.route("/_stats", get(stats_handler))
     };
 
-    #[cfg(not(feature = "cache"))]
-    let app = Router::new().route("/{image}", get(handler));
+    let app = app.with_state(state);
 
     let base_url = match &args.base_url {
         Some(base) => base,
@@ -55,20 +119,74 @@ async fn main() {
         args.port, base_url, args.port
     );
 
+    println!("Serving {}", roots.root().display());
+    for (name, dir) in roots.mounts() {
+        println!("-> /{}/ from {}", name, dir.display());
+    }
+
     #[cfg(feature = "cache")]
-    println!("Cache enabled with capacity: {}", args.cache_capacity);
+    println!(
+        "Cache enabled with budget: {} bytes across {} shards",
+        args.cache_max_bytes, args.cache_shards
+    );
 
     #[cfg(not(feature = "tls"))]
     {
         println!("WARNING: TLS disabled. Serving plain HTTP.");
-        serve_http(app, args.port).await;
+        #[cfg(unix)]
+        let unix_socket = args.unix_socket.map(|path| UnixSocketConfig {
+            path,
+            mode: args.unix_socket_mode,
+        });
+
+        serve_http(
+            app,
+            &args.bind,
+            #[cfg(unix)]
+            unix_socket.as_ref(),
+            &args.socket,
+            args.drain_timeout,
+        )
+        .await;
     }
 
     #[cfg(feature = "tls")]
     {
-        // We can safely unwrap because clap ensures cert_path exists if no_tls is false
-        let cert_path = args.cert_path.expect("Cert path required for HTTPS");
-        serve_https(app, args.port, cert_path).await;
+        let tls = TlsOptions {
+            cert_path: args.cert_path,
+            sni_dir: args.sni_dir,
+            cert_reload_interval: args.cert_reload_interval,
+            client_ca: args.client_ca,
+            client_auth: args.client_auth,
+            min_version: args.tls_min_version,
+            cipher_suites: args.cipher_suites,
+            alpn: args.alpn,
+        };
+
+        let redirect = args.http_port.map(|http_port| RedirectConfig {
+            addrs: args
+                .bind
+                .iter()
+                .map(|addr| SocketAddr::new(addr.ip(), http_port))
+                .collect(),
+            https_host: args.base_url.as_deref().map(|base| {
+                base_authority(base).unwrap_or_else(|| {
+                    eprintln!("Invalid --base-url {}: expected a host or URL", base);
+                    std::process::exit(1);
+                })
+            }),
+            https_port: args.bind.first().map_or(args.port, |addr| addr.port()),
+            acme_challenge_dir: args.acme_challenge_dir,
+        });
+
+        serve_https(
+            app,
+            &args.bind,
+            &args.socket,
+            tls,
+            redirect,
+            args.drain_timeout,
+        )
+        .await;
     }
 }
-
@@ -0,0 +1,368 @@
+//! WebAssembly plugins loaded from a directory at startup.
+//!
+//! A plugin is a core wasm module without imports that exports:
+//!
+//! - `memory`: its linear memory
+//! - `nano_alloc(len: u32) -> u32`: allocate `len` bytes and return their offset
+//! - `nano_name() -> u64`: offset (high 32 bits) and length (low 32 bits) of the UTF-8 query key
+//!   the plugin is registered under
+//! - `nano_params() -> u64` (optional): offset and length, packed the same way, of the other query
+//!   keys the plugin reads as `key\0key\0...`, e.g. `wm_opacity\0` for a plugin named `wm`
+//! - `nano_apply(pixels: u32, width: u32, height: u32, params: u32, params_len: u32) -> u32`:
+//!   transform the `width * height * 4` RGBA8 bytes at `pixels` in place and return 0 on success
+//!
+//! Query parameters are passed as `key\0value\0` pairs, limited to the keys some operation is
+//! registered under, the keys declared by `nano_params` and `to`. Every call runs in a fresh
+//! instance with a fuel and memory budget, so a misbehaving plugin fails the request instead of
+//! the server.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use image::DynamicImage;
+use wasmtime::{
+    Config, Engine, Instance, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use crate::error::{ImageServerError, Result};
+use crate::plugin::registry::PluginRegistry;
+use crate::plugin::{ImageOperation, OperationParams};
+
+/// Resources a single plugin invocation may consume
+#[derive(Debug, Clone, Copy)]
+pub struct PluginLimits {
+    /// Wasm fuel units, roughly one per executed instruction
+    pub fuel: u64,
+    /// Maximum size of the plugin's linear memory in bytes
+    pub max_memory: usize,
+}
+
+/// Longest query key, in bytes, a plugin may register under
+const MAX_NAME_LEN: usize = 256;
+
+/// Longest `nano_params` list, in bytes
+const MAX_PARAMS_LEN: usize = 4096;
+
+pub struct WasmOperation {
+    name: String,
+    params: Vec<String>,
+    instance_pre: InstancePre<StoreLimits>,
+    limits: PluginLimits,
+}
+
+impl WasmOperation {
+    pub fn load(engine: &Engine, path: &Path, limits: PluginLimits) -> Result<Self> {
+        let module = Module::from_file(engine, path).map_err(|e| plugin_error("compile", e))?;
+        Self::new(&module, limits)
+    }
+
+    fn new(module: &Module, limits: PluginLimits) -> Result<Self> {
+        let instance_pre = Linker::new(module.engine())
+            .instantiate_pre(module)
+            .map_err(|e| plugin_error("link", e))?;
+
+        let mut op = Self {
+            name: String::new(),
+            params: Vec::new(),
+            instance_pre,
+            limits,
+        };
+
+        let (mut store, instance) = op.instantiate()?;
+        op.name = exported_str(&instance, &mut store, "nano_name", MAX_NAME_LEN)?;
+
+        if instance.get_export(&mut store, "nano_params").is_some() {
+            let params = exported_str(&instance, &mut store, "nano_params", MAX_PARAMS_LEN)?;
+            op.params = params.split_terminator('\0').map(str::to_string).collect();
+            if op.params.iter().any(String::is_empty) {
+                return Err(plugin_error(
+                    "nano_params",
+                    "parameter keys must not be empty",
+                ));
+            }
+        }
+
+        Ok(op)
+    }
+
+    /// Query key the plugin is registered under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn instantiate(&self) -> Result<(Store<StoreLimits>, Instance)> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.limits.max_memory)
+            .instances(1)
+            .build();
+
+        let mut store = Store::new(self.instance_pre.module().engine(), limits);
+        store.limiter(|limits| limits);
+        store
+            .set_fuel(self.limits.fuel)
+            .map_err(|e| plugin_error("fuel", e))?;
+
+        let instance = self
+            .instance_pre
+            .instantiate(&mut store)
+            .map_err(|e| plugin_error("instantiate", e))?;
+
+        Ok((store, instance))
+    }
+}
+
+impl ImageOperation for WasmOperation {
+    fn params(&self) -> Vec<&str> {
+        self.params.iter().map(String::as_str).collect()
+    }
+
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        let mut rgba = img.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        let encoded_params = encode_params(params);
+
+        let (mut store, instance) = self.instantiate()?;
+        let memory = memory(&instance, &mut store)?;
+
+        let alloc = instance
+            .get_typed_func::<u32, u32>(&mut store, "nano_alloc")
+            .map_err(|e| plugin_error("nano_alloc", e))?;
+        let apply = instance
+            .get_typed_func::<(u32, u32, u32, u32, u32), u32>(&mut store, "nano_apply")
+            .map_err(|e| plugin_error("nano_apply", e))?;
+
+        let pixels_len = u32::try_from(rgba.len())
+            .map_err(|_| ImageServerError::PluginError("image too large for plugin".into()))?;
+        let params_len = encoded_params.len() as u32;
+
+        let pixels = alloc
+            .call(&mut store, pixels_len)
+            .map_err(|e| plugin_error("nano_alloc", e))?;
+        memory
+            .write(&mut store, pixels as usize, &rgba)
+            .map_err(|e| plugin_error("write pixels", e))?;
+
+        let params_ptr = alloc
+            .call(&mut store, params_len)
+            .map_err(|e| plugin_error("nano_alloc", e))?;
+        memory
+            .write(&mut store, params_ptr as usize, &encoded_params)
+            .map_err(|e| plugin_error("write params", e))?;
+
+        let status = apply
+            .call(&mut store, (pixels, width, height, params_ptr, params_len))
+            .map_err(|e| plugin_error(&self.name, e))?;
+
+        if status != 0 {
+            return Err(ImageServerError::PluginError(format!(
+                "{} returned status {}",
+                self.name, status
+            )));
+        }
+
+        memory
+            .read(&store, pixels as usize, &mut rgba)
+            .map_err(|e| plugin_error("read pixels", e))?;
+
+        Ok(DynamicImage::ImageRgba8(rgba))
+    }
+}
+
+/// Compile every `.wasm` file in `dir` and register it under the name it declares.
+///
+/// Modules that fail to load are reported and skipped. A module declaring a name that is empty or
+/// already has a meaning in queries fails the whole load, rather than shadowing a built-in
+/// operation or another plugin. Returns the number of plugins registered.
+pub fn load_plugins(
+    dir: &Path,
+    limits: PluginLimits,
+    registry: &mut PluginRegistry,
+) -> Result<usize> {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config).map_err(|e| plugin_error("engine", e))?;
+
+    let mut loaded = 0;
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("wasm") {
+            continue;
+        }
+
+        let op = match WasmOperation::load(&engine, &path, limits) {
+            Ok(op) => op,
+            Err(err) => {
+                eprintln!("Skipping plugin {}: {}", path.display(), err);
+                continue;
+            }
+        };
+
+        let name = op.name().to_string();
+        check_name(registry, &name).map_err(|reason| {
+            ImageServerError::PluginError(format!("{}: {reason}", path.display()))
+        })?;
+
+        registry.register(name.clone(), Arc::new(op));
+        println!("-> Loaded plugin '{}' from {}", name, path.display());
+        loaded += 1;
+    }
+
+    Ok(loaded)
+}
+
+// Plugins only add new query keys, they never take over one that already means something
+fn check_name(registry: &PluginRegistry, name: &str) -> std::result::Result<(), String> {
+    if name.is_empty() {
+        Err("the plugin name is empty".to_string())
+    } else if registry.get(name).is_some() {
+        Err(format!("'{name}' is already registered"))
+    } else if registry.is_reserved(name) {
+        Err(format!("'{name}' is a reserved query key"))
+    } else {
+        Ok(())
+    }
+}
+
+// UTF-8 string at the offset and length packed into the result of the export `name`
+fn exported_str(
+    instance: &Instance,
+    store: &mut Store<StoreLimits>,
+    name: &str,
+    max_len: usize,
+) -> Result<String> {
+    let packed = instance
+        .get_typed_func::<(), u64>(&mut *store, name)
+        .and_then(|export| export.call(&mut *store, ()))
+        .map_err(|e| plugin_error(name, e))?;
+
+    let (offset, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+    if len > max_len {
+        return Err(plugin_error(
+            name,
+            format!("{len} bytes exceeds the limit of {max_len}"),
+        ));
+    }
+
+    let memory = memory(instance, store)?;
+    let bytes = memory
+        .data(&*store)
+        .get(offset..offset + len)
+        .ok_or_else(|| plugin_error(name, "out of bounds of the plugin memory"))?;
+
+    std::str::from_utf8(bytes)
+        .map(str::to_string)
+        .map_err(|_| plugin_error(name, "not valid UTF-8"))
+}
+
+fn memory(instance: &Instance, store: &mut Store<StoreLimits>) -> Result<wasmtime::Memory> {
+    instance
+        .get_memory(store, "memory")
+        .ok_or_else(|| ImageServerError::PluginError("plugin does not export memory".into()))
+}
+
+fn encode_params(params: &OperationParams) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (key, value) in params.pairs() {
+        out.extend_from_slice(key.as_bytes());
+        out.push(0);
+        out.extend_from_slice(value.as_bytes());
+        out.push(0);
+    }
+    out
+}
+
+fn plugin_error(stage: &str, err: impl std::fmt::Display) -> ImageServerError {
+    ImageServerError::PluginError(format!("{stage}: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::{ProcessingLimits, consumed_params};
+
+    // Registered as `wm`, also reading `wm_opacity`
+    const WATERMARK: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 16) "wm")
+          (data (i32.const 32) "wm_opacity\00")
+          (func (export "nano_name") (result i64) (i64.const 0x1000000002))
+          (func (export "nano_params") (result i64) (i64.const 0x200000000b))
+          (func (export "nano_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "nano_apply") (param i32 i32 i32 i32 i32) (result i32) (i32.const 0)))
+    "#;
+
+    fn load(wat: &str) -> Result<WasmOperation> {
+        let engine = Engine::new(Config::new().consume_fuel(true)).unwrap();
+        let module = Module::new(&engine, wat::parse_str(wat).unwrap()).unwrap();
+        let limits = PluginLimits {
+            fuel: 1_000_000,
+            max_memory: 1 << 20,
+        };
+        WasmOperation::new(&module, limits)
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> OperationParams {
+        OperationParams::from(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn reads_declared_params() {
+        let op = load(WATERMARK).unwrap();
+
+        assert_eq!(op.name(), "wm");
+        assert_eq!(op.params(), ["wm_opacity"]);
+    }
+
+    #[test]
+    fn declared_params_are_kept_while_the_plugin_runs() {
+        let mut registry = PluginRegistry::with_inbuilt(ProcessingLimits::default());
+        registry.register("wm", Arc::new(load(WATERMARK).unwrap()));
+
+        let requested = params(&[("wm_opacity", "0.5"), ("cb", "1"), ("wm", "logo")]);
+        let consumed = consumed_params(&registry, requested);
+        assert_eq!(consumed.canonical(), "wm=logo&wm_opacity=0.5");
+
+        #[cfg(feature = "cache")]
+        assert_eq!(
+            crate::handler::cache_key(&registry, "a.png", &consumed),
+            ("a.png".to_string(), "wm=logo&wm_opacity=0.5".to_string())
+        );
+
+        // Without the plugin in the pipeline its parameters are dropped like any other key
+        let requested = params(&[("w", "10"), ("wm_opacity", "0.5")]);
+        assert_eq!(consumed_params(&registry, requested).canonical(), "w=10");
+    }
+
+    #[test]
+    fn names_with_a_meaning_in_queries_are_rejected() {
+        let mut registry = PluginRegistry::with_inbuilt(ProcessingLimits::default());
+        registry.register("wm", Arc::new(load(WATERMARK).unwrap()));
+
+        for name in ["", "to", "resfilter", "f_param", "t_param", "wm_opacity"] {
+            assert!(
+                check_name(&registry, name).is_err(),
+                "{name:?} was accepted"
+            );
+        }
+        for name in ["w", "h", "blur", "rotate", "filter", "wm"] {
+            let err = check_name(&registry, name).unwrap_err();
+            assert!(err.contains("already registered"), "{name}: {err}");
+        }
+        assert_eq!(check_name(&registry, "sepia"), Ok(()));
+    }
+
+    #[test]
+    fn empty_declared_params_are_rejected() {
+        let wat = WATERMARK.replace("wm_opacity\\00", "a\\00\\00b");
+        let Err(err) = load(&wat) else {
+            panic!("plugin with an empty parameter key loaded");
+        };
+        assert!(err.to_string().contains("must not be empty"), "{err}");
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use image::DynamicImage;
+use image::imageops::FilterType;
+
+use crate::error::{ImageServerError, Result};
+use crate::plugin::registry::PluginRegistry;
+use crate::plugin::{ImageOperation, OperationParams};
+use crate::processing::ProcessingLimits;
+
+/// Register every built-in operation with its query keys.
+///
+/// Operations run in registration order: resize, then filters, then transforms. `filter=` and
+/// `transform=` select one of the filters or transforms by name, with its value in `f_param` or
+/// `t_param`.
+pub fn register(registry: &mut PluginRegistry) {
+    let limits = *registry.limits();
+
+    let resize: Arc<dyn ImageOperation> = Arc::new(Resize { limits });
+    registry.register("w", resize.clone());
+    registry.register("h", resize);
+
+    registry.register("blur", Arc::new(Blur { limits }));
+    registry.register("bw", Arc::new(Grayscale));
+    registry.register("brighten", Arc::new(Brighten));
+    registry.register("contrast", Arc::new(Contrast));
+    registry.register("filter", Arc::new(Filter { limits }));
+
+    registry.register("flip", Arc::new(Flip));
+    registry.register("rotate", Arc::new(Rotate));
+    registry.register("transform", Arc::new(Transform));
+}
+
+/// `w=<px>&h=<px>&resfilter=<filter>`, a single dimension keeps the aspect ratio
+pub struct Resize {
+    pub limits: ProcessingLimits,
+}
+
+impl ImageOperation for Resize {
+    fn params(&self) -> Vec<&str> {
+        vec!["resfilter"]
+    }
+
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        let filter = match params.get("resfilter") {
+            None | Some("lanczos") => FilterType::Lanczos3,
+            Some("nearest") => FilterType::Nearest,
+            Some("triangle") => FilterType::Triangle,
+            Some("catmullrom") => FilterType::CatmullRom,
+            Some("gaussian") => FilterType::Gaussian,
+            Some(other) => return Err(invalid(format!("unknown resfilter: {other}"))),
+        };
+
+        let (width, height) = match (params.parse::<u32>("w")?, params.parse::<u32>("h")?) {
+            (Some(0), _) | (_, Some(0)) => {
+                return Err(invalid("width and height must be non-zero".into()));
+            }
+            (Some(w), Some(h)) => (w, h),
+            (Some(w), None) => (w, scale(img.height(), w, img.width())),
+            (None, Some(h)) => (scale(img.width(), h, img.height()), h),
+            (None, None) => return Ok(img),
+        };
+
+        // Checked before resizing, the output buffer is allocated up front
+        self.limits.check_pixels(width, height)?;
+        Ok(img.resize_exact(width, height, filter))
+    }
+}
+
+// `side * to / from`, rounded and at least 1 pixel, saturating at `u32::MAX`
+fn scale(side: u32, to: u32, from: u32) -> u32 {
+    let scaled = (u64::from(side) * u64::from(to) + u64::from(from) / 2) / u64::from(from.max(1));
+    scaled.clamp(1, u64::from(u32::MAX)) as u32
+}
+
+/// `blur=<sigma>`, defaults to 1.0 when empty
+pub struct Blur {
+    pub limits: ProcessingLimits,
+}
+
+impl ImageOperation for Blur {
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        let sigma = parse_or(params, "blur", 1.0f32)?;
+        // The kernel grows with sigma, NaN fails the range check as well
+        if !(0.0..=self.limits.max_blur_sigma).contains(&sigma) {
+            return Err(invalid(format!(
+                "blur must be between 0 and {}, got {sigma}",
+                self.limits.max_blur_sigma
+            )));
+        }
+        Ok(img.blur(sigma))
+    }
+}
+
+/// `rotate=90|180|270`, defaults to 90 when empty
+pub struct Rotate;
+
+impl ImageOperation for Rotate {
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        match parse_or(params, "rotate", 90u32)? {
+            90 => Ok(img.rotate90()),
+            180 => Ok(img.rotate180()),
+            270 => Ok(img.rotate270()),
+            other => Err(invalid(format!(
+                "rotation must be 90, 180 or 270, got {other}"
+            ))),
+        }
+    }
+}
+
+/// `flip=h|v`
+pub struct Flip;
+
+impl ImageOperation for Flip {
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        match params.get("flip") {
+            Some("h") => Ok(img.fliph()),
+            Some("v") => Ok(img.flipv()),
+            other => Err(invalid(format!(
+                "flip must be h or v, got {}",
+                other.unwrap_or_default()
+            ))),
+        }
+    }
+}
+
+/// `bw`, converts to grayscale
+pub struct Grayscale;
+
+impl ImageOperation for Grayscale {
+    fn apply(&self, img: DynamicImage, _params: &OperationParams) -> Result<DynamicImage> {
+        Ok(img.grayscale())
+    }
+}
+
+/// `brighten=<amount>`, negative values darken
+pub struct Brighten;
+
+impl ImageOperation for Brighten {
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        let value = parse_or(params, "brighten", 10i32)?;
+        Ok(img.brighten(value))
+    }
+}
+
+/// `contrast=<amount>`, negative values reduce contrast
+pub struct Contrast;
+
+impl ImageOperation for Contrast {
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        let value = parse_or(params, "contrast", 10.0f32)?;
+        Ok(img.adjust_contrast(value))
+    }
+}
+
+/// `filter=blur|bw|brighten|contrast&f_param=<value>`, the filters behind a single key
+pub struct Filter {
+    pub limits: ProcessingLimits,
+}
+
+impl ImageOperation for Filter {
+    fn params(&self) -> Vec<&str> {
+        vec!["f_param"]
+    }
+
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        let param = params.parse::<f32>("f_param")?;
+        let value = |default: f32| param.unwrap_or(default).to_string();
+
+        match params.get("filter").unwrap_or_default() {
+            "blur" => Blur {
+                limits: self.limits,
+            }
+            .apply(img, &single("blur", value(1.0))),
+            "bw" => Grayscale.apply(img, params),
+            // `f_param` is a float for every filter, brighten truncates it
+            "brighten" => Brighten.apply(
+                img,
+                &single("brighten", (param.unwrap_or(10.0) as i32).to_string()),
+            ),
+            "contrast" => Contrast.apply(img, &single("contrast", value(10.0))),
+            other => Err(invalid(format!("unknown filter: {other}"))),
+        }
+    }
+}
+
+/// `transform=fliph|flipv|rotate&t_param=<degrees>`, the transforms behind a single key
+pub struct Transform;
+
+impl ImageOperation for Transform {
+    fn params(&self) -> Vec<&str> {
+        vec!["t_param"]
+    }
+
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage> {
+        match params.get("transform").unwrap_or_default() {
+            "fliph" => Flip.apply(img, &single("flip", "h".into())),
+            "flipv" => Flip.apply(img, &single("flip", "v".into())),
+            "rotate" => {
+                let degrees = params.parse::<u32>("t_param")?.unwrap_or(90);
+                Rotate.apply(img, &single("rotate", degrees.to_string()))
+            }
+            other => Err(invalid(format!("unknown transform: {other}"))),
+        }
+    }
+}
+
+// Parameters for running one built-in on behalf of `filter=` or `transform=`
+fn single(key: &str, value: String) -> OperationParams {
+    OperationParams::from(vec![(key.to_string(), value)])
+}
+
+// Empty values (e.g. `?blur`) fall back to the default
+fn parse_or<T: std::str::FromStr>(params: &OperationParams, key: &str, default: T) -> Result<T> {
+    match params.get(key) {
+        None | Some("") => Ok(default),
+        Some(_) => Ok(params.parse(key)?.unwrap_or(default)),
+    }
+}
+
+fn invalid(msg: String) -> ImageServerError {
+    ImageServerError::InvalidOperation(msg)
+}
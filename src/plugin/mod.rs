@@ -0,0 +1,138 @@
+use std::str::FromStr;
+
+use image::DynamicImage;
+use serde::Deserialize;
+
+use crate::error::{ImageServerError, Result};
+
+pub mod inbuilt;
+pub mod registry;
+
+#[cfg(feature = "wasm")]
+pub mod external;
+
+/// A transformation that can be applied to a decoded image.
+///
+/// Operations are registered in a [`registry::PluginRegistry`] under one or more query keys and
+/// read whatever parameters they need from the request's [`OperationParams`].
+pub trait ImageOperation: Send + Sync {
+    fn apply(&self, img: DynamicImage, params: &OperationParams) -> Result<DynamicImage>;
+
+    /// Query keys the operation reads besides the ones it is registered under, such as `resfilter`
+    /// for resize. Any other key is dropped before the operation runs
+    fn params(&self) -> Vec<&str> {
+        Vec::new()
+    }
+}
+
+/// Query string parameters in the order they appeared in the request
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(from = "Vec<(String, String)>")]
+pub struct OperationParams(Vec<(String, String)>);
+
+impl OperationParams {
+    /// Value of the first occurrence of `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Parse the value of `key`, failing with `InvalidOperation` if it is present but malformed
+    pub fn parse<T: FromStr>(&self, key: &str) -> Result<Option<T>> {
+        self.get(key)
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    ImageServerError::InvalidOperation(format!("invalid value for {key}: {value}"))
+                })
+            })
+            .transpose()
+    }
+
+    /// Keep only the pairs whose key satisfies `keep`
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        self.0.retain(|(key, _)| keep(key));
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(|(k, _)| k.as_str())
+    }
+
+    pub fn pairs(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Order-independent `key=value&...` rendering, sorted by key.
+    ///
+    /// Only the first occurrence of a key is kept since that is the one [`Self::get`] returns.
+    pub fn canonical(&self) -> String {
+        let mut pairs: Vec<(&str, &str)> = Vec::with_capacity(self.0.len());
+        for (key, value) in self.pairs() {
+            if !pairs.iter().any(|(k, _)| *k == key) {
+                pairs.push((key, value));
+            }
+        }
+        pairs.sort_unstable_by_key(|(key, _)| *key);
+
+        pairs
+            .iter()
+            .map(|(key, value)| format!("{}={}", escape(key), escape(value)))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+}
+
+// Keeps `&` and `=` inside keys or values from producing ambiguous renderings
+fn escape(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('&', "%26")
+        .replace('=', "%3D")
+}
+
+impl From<Vec<(String, String)>> for OperationParams {
+    fn from(pairs: Vec<(String, String)>) -> Self {
+        Self(pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> OperationParams {
+        OperationParams::from(
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[test]
+    fn canonical_sorts_by_key() {
+        let a = params(&[("w", "10"), ("blur", "2"), ("h", "5")]);
+        let b = params(&[("h", "5"), ("w", "10"), ("blur", "2")]);
+
+        assert_eq!(a.canonical(), "blur=2&h=5&w=10");
+        assert_eq!(a.canonical(), b.canonical());
+    }
+
+    #[test]
+    fn canonical_keeps_the_first_occurrence() {
+        let repeated = params(&[("w", "10"), ("w", "20")]);
+
+        assert_eq!(repeated.get("w"), Some("10"));
+        assert_eq!(repeated.canonical(), "w=10");
+    }
+
+    #[test]
+    fn canonical_escapes_separators() {
+        let tricky = params(&[("a", "1&b=2")]);
+        let split = params(&[("a", "1"), ("b", "2")]);
+
+        assert_eq!(tricky.canonical(), "a=1%26b%3D2");
+        assert_ne!(tricky.canonical(), split.canonical());
+        assert_eq!(params(&[("a", "100%")]).canonical(), "a=100%25");
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::plugin::{ImageOperation, OperationParams, inbuilt};
+use crate::processing::ProcessingLimits;
+
+/// Maps query keys to the image operations they trigger
+#[derive(Default)]
+pub struct PluginRegistry {
+    operations: HashMap<String, Arc<dyn ImageOperation>>,
+    // Keys in registration order, which is also the order operations run in
+    order: Vec<String>,
+    limits: ProcessingLimits,
+}
+
+impl PluginRegistry {
+    pub fn new(limits: ProcessingLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
+    /// Registry populated with the operations from [`inbuilt`]
+    pub fn with_inbuilt(limits: ProcessingLimits) -> Self {
+        let mut registry = Self::new(limits);
+        inbuilt::register(&mut registry);
+        registry
+    }
+
+    /// Limits every request processed with this registry runs under
+    pub fn limits(&self) -> &ProcessingLimits {
+        &self.limits
+    }
+
+    /// Register `op` under `key`, returning the operation previously registered for it.
+    ///
+    /// Replacing an operation keeps the position of the original in the pipeline.
+    pub fn register(
+        &mut self,
+        key: impl Into<String>,
+        op: Arc<dyn ImageOperation>,
+    ) -> Option<Arc<dyn ImageOperation>> {
+        let key = key.into();
+        let previous = self.operations.insert(key.clone(), op);
+        if previous.is_none() {
+            self.order.push(key);
+        }
+        previous
+    }
+
+    /// Whether `key` already has a meaning in a query: it triggers an operation, is a parameter one
+    /// of them reads, or is `to`
+    pub fn is_reserved(&self, key: &str) -> bool {
+        key == "to"
+            || self.operations.contains_key(key)
+            || self
+                .operations
+                .values()
+                .any(|op| op.params().contains(&key))
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Arc<dyn ImageOperation>> {
+        self.operations.get(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.order.iter().map(|k| k.as_str())
+    }
+
+    /// Operations triggered by `params` in registration order, so the order of the query
+    /// string does not change the result.
+    ///
+    /// An operation registered under several keys (e.g. resize on `w` and `h`) runs only once.
+    pub fn pipeline(&self, params: &OperationParams) -> Vec<Arc<dyn ImageOperation>> {
+        let mut ops: Vec<Arc<dyn ImageOperation>> = Vec::new();
+
+        for key in &self.order {
+            if params.get(key).is_some()
+                && let Some(op) = self.operations.get(key)
+                && !ops.iter().any(|existing| Arc::ptr_eq(existing, op))
+            {
+                ops.push(op.clone());
+            }
+        }
+
+        ops
+    }
+}
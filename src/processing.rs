@@ -0,0 +1,133 @@
+use std::io::Cursor;
+
+use bytes::Bytes;
+use image::error::ImageError;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits};
+
+use crate::error::{ImageServerError, Result};
+use crate::plugin::OperationParams;
+use crate::plugin::registry::PluginRegistry;
+
+/// Bounds on the work a single request can cause, so oversized parameters are rejected instead of
+/// exhausting memory or CPU
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProcessingLimits {
+    /// Largest decoded source or resized output, in pixels
+    pub max_pixels: u64,
+    /// Largest `blur=` sigma
+    pub max_blur_sigma: f32,
+}
+
+impl Default for ProcessingLimits {
+    fn default() -> Self {
+        Self {
+            max_pixels: 40_000_000,
+            max_blur_sigma: 50.0,
+        }
+    }
+}
+
+impl ProcessingLimits {
+    /// Fails with `InvalidOperation` if a `width` x `height` image is over `max_pixels`
+    pub fn check_pixels(&self, width: u32, height: u32) -> Result<()> {
+        let pixels = u64::from(width) * u64::from(height);
+        if pixels > self.max_pixels {
+            return Err(ImageServerError::InvalidOperation(format!(
+                "{width}x{height} exceeds the limit of {} pixels",
+                self.max_pixels
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if `params` request any operation, otherwise the original bytes can be served as-is
+pub fn has_operations(registry: &PluginRegistry, params: &OperationParams) -> bool {
+    params.get("to").is_some() || params.keys().any(|key| registry.get(key).is_some())
+}
+
+/// `params` without the keys no operation reads, e.g. cache busters like `cb=1`, so they can
+/// neither change the cache key nor reach the operations
+pub fn consumed_params(registry: &PluginRegistry, mut params: OperationParams) -> OperationParams {
+    let pipeline = registry.pipeline(&params);
+    params.retain(|key| {
+        key == "to"
+            || registry.get(key).is_some()
+            || pipeline.iter().any(|op| op.params().contains(&key))
+    });
+    params
+}
+
+/// Decode `bytes`, run the operations registered for the query keys in `params` in registry order,
+/// then re-encode the result in the format requested by `to=` (or the source format).
+///
+/// Returns the content type of the encoded output together with its bytes.
+pub fn process_image(
+    bytes: &[u8],
+    params: &OperationParams,
+    registry: &PluginRegistry,
+) -> Result<(String, Bytes)> {
+    let source_format = image::guess_format(bytes).map_err(|_| ImageServerError::InvalidFormat)?;
+    let mut img = decode(bytes, source_format, registry.limits())?;
+
+    for op in registry.pipeline(params) {
+        img = op.apply(img, params)?;
+    }
+
+    let target_format = match params.get("to") {
+        Some(to) => parse_format(to)?,
+        None => source_format,
+    };
+
+    encode(img, target_format)
+}
+
+// The dimensions are checked before any pixel is decoded
+fn decode(bytes: &[u8], format: ImageFormat, limits: &ProcessingLimits) -> Result<DynamicImage> {
+    let mut decoder_limits = Limits::default();
+    // 16 bytes is the widest pixel the decoders produce (RGBA, 32-bit float)
+    decoder_limits.max_alloc = Some(limits.max_pixels.saturating_mul(16));
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(decoder_limits);
+
+    let decoder = reader.into_decoder().map_err(decode_error)?;
+    let (width, height) = decoder.dimensions();
+    limits.check_pixels(width, height)?;
+
+    DynamicImage::from_decoder(decoder).map_err(decode_error)
+}
+
+fn decode_error(err: ImageError) -> ImageServerError {
+    match err {
+        ImageError::Limits(e) => {
+            ImageServerError::InvalidOperation(format!("image exceeds processing limits: {e}"))
+        }
+        e => ImageServerError::ProcessingError(format!("decode failed: {e}")),
+    }
+}
+
+fn parse_format(to: &str) -> Result<ImageFormat> {
+    ImageFormat::from_extension(to)
+        .filter(|format| format.writing_enabled())
+        .ok_or_else(|| {
+            ImageServerError::InvalidOperation(format!("unsupported output format: {to}"))
+        })
+}
+
+fn encode(img: DynamicImage, format: ImageFormat) -> Result<(String, Bytes)> {
+    // JPEG has no alpha channel
+    let img = match format {
+        ImageFormat::Jpeg if img.color().has_alpha() => DynamicImage::ImageRgb8(img.to_rgb8()),
+        _ => img,
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    img.write_to(&mut out, format)
+        .map_err(|e| ImageServerError::ProcessingError(format!("encode failed: {e}")))?;
+
+    Ok((
+        format.to_mime_type().to_string(),
+        Bytes::from(out.into_inner()),
+    ))
+}
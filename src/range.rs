@@ -0,0 +1,226 @@
+use std::ops::RangeInclusive;
+use std::time::SystemTime;
+
+use axum::http::{HeaderMap, header};
+use bytes::Bytes;
+
+// Requests with more ranges than this are served in full rather than as a huge multipart body
+const MAX_RANGES: usize = 32;
+
+/// Outcome of evaluating a `Range` header against a representation of a known length
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    /// No usable `Range` header, send the whole body
+    Full,
+    /// Byte ranges to send as inclusive offsets, in ascending order with overlapping and adjacent
+    /// ranges merged
+    Partial(Vec<RangeInclusive<u64>>),
+    /// None of the requested ranges overlap the body, send `416`
+    Unsatisfiable,
+}
+
+/// Evaluate the `Range` and `If-Range` headers of a request for a body of `len` bytes.
+///
+/// Malformed headers and units other than `bytes` are ignored as RFC 9110 requires, as is a
+/// `Range` whose `If-Range` validator no longer matches.
+pub fn evaluate(
+    headers: &HeaderMap,
+    len: u64,
+    etag: &str,
+    last_modified: Option<SystemTime>,
+) -> RangeRequest {
+    let Some(range) = headers.get(header::RANGE).and_then(|v| v.to_str().ok()) else {
+        return RangeRequest::Full;
+    };
+
+    if let Some(if_range) = headers.get(header::IF_RANGE)
+        && !if_range_matches(if_range.to_str().unwrap_or_default(), etag, last_modified)
+    {
+        return RangeRequest::Full;
+    }
+
+    parse(range, len)
+}
+
+/// Parse a `Range` header value such as `bytes=0-99,200-,-50` for a body of `len` bytes
+pub fn parse(value: &str, len: u64) -> RangeRequest {
+    let Some(specs) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim) {
+        let Some((start, end)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+
+        let range = match (start.trim(), end.trim()) {
+            // Suffix range: the last `n` bytes
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => None,
+                Ok(n) if len > 0 => Some(len.saturating_sub(n)..=len - 1),
+                Ok(_) => None,
+                Err(_) => return RangeRequest::Full,
+            },
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return RangeRequest::Full;
+                };
+                let end = match end {
+                    "" => u64::MAX,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end,
+                        _ => return RangeRequest::Full,
+                    },
+                };
+
+                (start < len).then(|| start..=end.min(len - 1))
+            }
+        };
+
+        ranges.extend(range);
+    }
+
+    // Merged first, so repeating a range can neither duplicate bytes nor count against the limit
+    let ranges = merge(ranges);
+
+    if ranges.is_empty() {
+        RangeRequest::Unsatisfiable
+    } else if ranges.len() > MAX_RANGES {
+        RangeRequest::Full
+    } else {
+        RangeRequest::Partial(ranges)
+    }
+}
+
+// Sort `ranges` and merge the ones that overlap or touch
+fn merge(mut ranges: Vec<RangeInclusive<u64>>) -> Vec<RangeInclusive<u64>> {
+    ranges.sort_unstable_by_key(|range| *range.start());
+
+    let mut merged: Vec<RangeInclusive<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end().saturating_add(1) >= *range.start() => {
+                *last = *last.start()..=*last.end().max(range.end());
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// `Content-Range` value for `range` of a body of `len` bytes
+pub fn content_range(range: &RangeInclusive<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start(), range.end(), len)
+}
+
+/// Header of one part of a `multipart/byteranges` body, followed by the bytes of `range`
+pub fn multipart_header(
+    boundary: &str,
+    content_type: &str,
+    range: &RangeInclusive<u64>,
+    len: u64,
+) -> Bytes {
+    Bytes::from(format!(
+        "--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+        boundary,
+        content_type,
+        content_range(range, len)
+    ))
+}
+
+/// Closing delimiter of a `multipart/byteranges` body
+pub fn multipart_end(boundary: &str) -> Bytes {
+    Bytes::from(format!("--{}--\r\n", boundary))
+}
+
+// If-Range needs a strong match on the entity tag or an exact match on the date
+fn if_range_matches(if_range: &str, etag: &str, last_modified: Option<SystemTime>) -> bool {
+    let if_range = if_range.trim();
+
+    if if_range.starts_with('"') {
+        return if_range == etag;
+    }
+
+    match (httpdate::parse_http_date(if_range), last_modified) {
+        (Ok(date), Some(last_modified)) => {
+            httpdate::fmt_http_date(date) == httpdate::fmt_http_date(last_modified)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(ranges: &[RangeInclusive<u64>]) -> RangeRequest {
+        RangeRequest::Partial(ranges.to_vec())
+    }
+
+    #[test]
+    fn parses_bounded_and_open_ranges() {
+        assert_eq!(parse("bytes=0-99", 1000), partial(&[0..=99]));
+        assert_eq!(parse("bytes=900-", 1000), partial(&[900..=999]));
+        assert_eq!(parse("bytes=900-5000", 1000), partial(&[900..=999]));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse("bytes=-100", 1000), partial(&[900..=999]));
+        assert_eq!(parse("bytes=-5000", 1000), partial(&[0..=999]));
+        assert_eq!(parse("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn start_past_the_end_is_unsatisfiable() {
+        assert_eq!(parse("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=2000-3000", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=2000-3000,0-0", 1000), partial(&[0..=0]));
+    }
+
+    #[test]
+    fn end_before_start_is_ignored() {
+        assert_eq!(parse("bytes=500-100", 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        assert_eq!(parse("items=0-99", 1000), RangeRequest::Full);
+        assert_eq!(parse("bytes=abc", 1000), RangeRequest::Full);
+        assert_eq!(parse("bytes=0-99,x-", 1000), RangeRequest::Full);
+        assert_eq!(parse("bytes=--5", 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn empty_body_is_unsatisfiable() {
+        assert_eq!(parse("bytes=0-", 0), RangeRequest::Unsatisfiable);
+        assert_eq!(parse("bytes=-10", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn overlapping_and_adjacent_ranges_are_merged() {
+        assert_eq!(
+            parse("bytes=500-599,0-99,50-149,150-199", 1000),
+            partial(&[0..=199, 500..=599])
+        );
+        assert_eq!(parse("bytes=-100,900-", 1000), partial(&[900..=999]));
+        assert_eq!(parse("bytes=0-,10-20", 1000), partial(&[0..=999]));
+    }
+
+    #[test]
+    fn repeated_ranges_do_not_count_against_the_limit() {
+        let repeated = vec!["0-0"; MAX_RANGES * 4].join(",");
+        assert_eq!(parse(&format!("bytes={repeated}"), 1000), partial(&[0..=0]));
+
+        let distinct = (0..=MAX_RANGES as u64)
+            .map(|i| format!("{}-{}", i * 10, i * 10))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(
+            parse(&format!("bytes={distinct}"), 1000),
+            RangeRequest::Full
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::{self, SeekFrom};
+use std::ops::RangeInclusive;
+use std::time::SystemTime;
+
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use bytes::Bytes;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+use crate::body::ImageResponse;
+use crate::conditional::is_not_modified;
+use crate::error::ImageServerError;
+use crate::range::{self, RangeRequest};
+
+// Read size used when streaming files from disk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type ByteStream = BoxStream<'static, io::Result<Bytes>>;
+
+/// `200` with the image, `206`/`416` for range requests, or `304` without a body when the
+/// client's validators still match
+pub async fn image_response(request_headers: &HeaderMap, image: ImageResponse) -> Response {
+    let (content_type, etag, last_modified, len) = match &image {
+        ImageResponse::Buffered(body) => (
+            body.content_type.clone(),
+            body.etag.clone(),
+            body.last_modified,
+            body.bytes.len() as u64,
+        ),
+        ImageResponse::Streamed(file) => (
+            file.content_type.clone(),
+            file.etag.clone(),
+            file.last_modified,
+            file.len,
+        ),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    insert_validators(&mut headers, &etag, last_modified);
+
+    if is_not_modified(request_headers, &etag, last_modified) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    let result = match range::evaluate(request_headers, len, &etag, last_modified) {
+        RangeRequest::Full => {
+            insert_header(&mut headers, header::CONTENT_TYPE, &content_type);
+            insert_header(&mut headers, header::CONTENT_LENGTH, &len.to_string());
+            full_body(image, len)
+                .await
+                .map(|body| (StatusCode::OK, headers, body).into_response())
+        }
+        RangeRequest::Unsatisfiable => {
+            insert_header(
+                &mut headers,
+                header::CONTENT_RANGE,
+                &format!("bytes */{}", len),
+            );
+            Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response())
+        }
+        RangeRequest::Partial(ranges) => {
+            partial_body(&image, &ranges, &content_type, &etag, len, &mut headers)
+                .await
+                .map(|body| (StatusCode::PARTIAL_CONTENT, headers, body).into_response())
+        }
+    };
+
+    result.unwrap_or_else(|err| error_response(err.into()))
+}
+
+pub fn error_response(err: ImageServerError) -> Response {
+    let status_code =
+        StatusCode::from_u16(err.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let message = err.user_message();
+
+    eprintln!("Error serving image: {}", err);
+
+    (status_code, message).into_response()
+}
+
+async fn full_body(image: ImageResponse, len: u64) -> io::Result<Body> {
+    match image {
+        ImageResponse::Buffered(body) => Ok(Body::from(body.bytes)),
+        ImageResponse::Streamed(_) if len == 0 => Ok(Body::empty()),
+        ImageResponse::Streamed(_) => {
+            Ok(Body::from_stream(range_stream(&image, 0..=len - 1).await?))
+        }
+    }
+}
+
+async fn partial_body(
+    image: &ImageResponse,
+    ranges: &[RangeInclusive<u64>],
+    content_type: &str,
+    etag: &str,
+    len: u64,
+    headers: &mut HeaderMap,
+) -> io::Result<Body> {
+    if let [range] = ranges {
+        insert_header(headers, header::CONTENT_TYPE, content_type);
+        insert_header(
+            headers,
+            header::CONTENT_RANGE,
+            &range::content_range(range, len),
+        );
+        insert_header(
+            headers,
+            header::CONTENT_LENGTH,
+            &range_len(range).to_string(),
+        );
+        return Ok(Body::from_stream(range_stream(image, range.clone()).await?));
+    }
+
+    let boundary = format!("nano_image_server_{}", etag.trim_matches('"'));
+    insert_header(
+        headers,
+        header::CONTENT_TYPE,
+        &format!("multipart/byteranges; boundary={}", boundary),
+    );
+
+    let mut parts: Vec<ByteStream> = Vec::with_capacity(ranges.len() * 2 + 1);
+    let mut total = 0;
+    for range in ranges {
+        let part_header = range::multipart_header(&boundary, content_type, range, len);
+        total += part_header.len() as u64 + range_len(range) + 2;
+
+        parts.push(once(part_header));
+        parts.push(range_stream(image, range.clone()).await?);
+        parts.push(once(Bytes::from_static(b"\r\n")));
+    }
+    let end = range::multipart_end(&boundary);
+    total += end.len() as u64;
+    parts.push(once(end));
+
+    insert_header(headers, header::CONTENT_LENGTH, &total.to_string());
+    Ok(Body::from_stream(stream::iter(parts).flatten()))
+}
+
+// Bytes of `range` from memory, or read from disk in chunks for streamed files
+async fn range_stream(image: &ImageResponse, range: RangeInclusive<u64>) -> io::Result<ByteStream> {
+    match image {
+        ImageResponse::Buffered(body) => Ok(once(
+            body.bytes
+                .slice(*range.start() as usize..=*range.end() as usize),
+        )),
+        ImageResponse::Streamed(file) => {
+            let mut handle = File::open(&file.path).await?;
+            handle.seek(SeekFrom::Start(*range.start())).await?;
+            let reader = handle.take(range_len(&range));
+            Ok(ReaderStream::with_capacity(reader, CHUNK_SIZE).boxed())
+        }
+    }
+}
+
+fn once(bytes: Bytes) -> ByteStream {
+    stream::once(async move { Ok(bytes) }).boxed()
+}
+
+fn range_len(range: &RangeInclusive<u64>) -> u64 {
+    range.end() - range.start() + 1
+}
+
+fn insert_validators(headers: &mut HeaderMap, etag: &str, last_modified: Option<SystemTime>) {
+    insert_header(headers, header::ETAG, etag);
+    if let Some(last_modified) = last_modified {
+        insert_header(
+            headers,
+            header::LAST_MODIFIED,
+            &httpdate::fmt_http_date(last_modified),
+        );
+    }
+}
+
+fn insert_header(headers: &mut HeaderMap, name: header::HeaderName, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name, value);
+    }
+}
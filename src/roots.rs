@@ -0,0 +1,59 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directories images are served from, canonicalized once at startup.
+///
+/// A request whose first path segment names a mount is resolved inside that mount's directory,
+/// every other request inside the root.
+#[derive(Debug)]
+pub struct ImageRoots {
+    root: PathBuf,
+    mounts: Vec<(String, PathBuf)>,
+}
+
+impl ImageRoots {
+    pub fn new(root: &Path, mounts: &[(String, PathBuf)]) -> io::Result<Self> {
+        Ok(Self {
+            root: canonical_dir(root)?,
+            mounts: mounts
+                .iter()
+                .map(|(name, dir)| Ok((name.clone(), canonical_dir(dir)?)))
+                .collect::<io::Result<_>>()?,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn mounts(&self) -> impl Iterator<Item = (&str, &Path)> {
+        self.mounts
+            .iter()
+            .map(|(name, dir)| (name.as_str(), dir.as_path()))
+    }
+
+    /// Split a request path into the directory it is served from and the path relative to it
+    pub fn resolve<'a>(&self, image: &'a str) -> (&Path, &'a str) {
+        if let Some((prefix, rest)) = image.split_once('/')
+            && let Some((_, dir)) = self.mounts.iter().find(|(name, _)| name == prefix)
+        {
+            return (dir, rest);
+        }
+
+        (&self.root, image)
+    }
+}
+
+fn canonical_dir(dir: &Path) -> io::Result<PathBuf> {
+    let canonical = std::fs::canonicalize(dir)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+
+    if !canonical.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            format!("{} is not a directory", dir.display()),
+        ));
+    }
+
+    Ok(canonical)
+}
@@ -0,0 +1,434 @@
+use std::fmt::Write;
+use std::io;
+use std::task::{Context, Poll};
+
+use axum::http::Request;
+use axum_server::accept::Accept;
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use futures_util::future::BoxFuture;
+use rustls::pki_types::CertificateDer;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower_service::Service;
+
+/// Verified client certificate of a mutual TLS connection.
+///
+/// Added to the extensions of every request on connections that presented a certificate, so
+/// handlers can take an `Option<Extension<ClientIdentity>>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Subject distinguished name in RFC 4514 form, e.g. `CN=billing,O=Example`
+    pub subject: String,
+}
+
+impl ClientIdentity {
+    /// Identity of the leaf of the certificate chain a client presented, if its subject can be
+    /// read. Only called once rustls has verified the chain against the client CAs
+    pub fn from_peer_certificates(certs: &[CertificateDer<'_>]) -> Option<Self> {
+        let subject = subject(certs.first()?)?;
+        Some(Self { subject })
+    }
+}
+
+/// TLS acceptor that attaches the client certificate subject to the connection's requests
+#[derive(Clone)]
+pub struct ClientCertAcceptor {
+    inner: RustlsAcceptor,
+}
+
+impl ClientCertAcceptor {
+    pub fn new(config: RustlsConfig) -> Self {
+        Self {
+            inner: RustlsAcceptor::new(config),
+        }
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = <RustlsAcceptor as Accept<I, S>>::Stream;
+    type Service = WithClientIdentity<S>;
+    type Future = BoxFuture<'static, io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let handshake = self.inner.accept(stream, service);
+
+        Box::pin(async move {
+            let (stream, service) = handshake.await?;
+
+            let identity = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(ClientIdentity::from_peer_certificates);
+
+            Ok((
+                stream,
+                WithClientIdentity {
+                    inner: service,
+                    identity,
+                },
+            ))
+        })
+    }
+}
+
+/// Per-connection service that inserts the [`ClientIdentity`] into each request
+#[derive(Clone)]
+pub struct WithClientIdentity<S> {
+    inner: S,
+    identity: Option<ClientIdentity>,
+}
+
+impl<S, B> Service<Request<B>> for WithClientIdentity<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        if let Some(identity) = &self.identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        self.inner.call(request)
+    }
+}
+
+/// Subject of a DER certificate as an RFC 4514 string
+fn subject(cert: &[u8]) -> Option<String> {
+    let (_, cert, _) = read_tlv(cert)?;
+    let (_, mut tbs, _) = read_tlv(cert)?;
+
+    // Optional [0] version, then serial, signature algorithm, issuer and validity
+    if tbs.first() == Some(&0xa0) {
+        tbs = read_tlv(tbs)?.2;
+    }
+    for _ in 0..4 {
+        tbs = read_tlv(tbs)?.2;
+    }
+
+    let (SEQUENCE, name, _) = read_tlv(tbs)? else {
+        return None;
+    };
+
+    // RDNs are listed most significant first, RFC 4514 strings start with the last one
+    let mut rdns = Vec::new();
+    let mut rest = name;
+    while !rest.is_empty() {
+        let (SET, rdn, next) = read_tlv(rest)? else {
+            return None;
+        };
+        rest = next;
+
+        let mut attributes = Vec::new();
+        let mut rdn = rdn;
+        while !rdn.is_empty() {
+            let (SEQUENCE, attribute, next) = read_tlv(rdn)? else {
+                return None;
+            };
+            rdn = next;
+            attributes.push(attribute_string(attribute)?);
+        }
+        rdns.push(attributes.join("+"));
+    }
+
+    rdns.reverse();
+    Some(rdns.join(","))
+}
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const OID: u8 = 0x06;
+
+fn attribute_string(attribute: &[u8]) -> Option<String> {
+    let (OID, oid, value) = read_tlv(attribute)? else {
+        return None;
+    };
+    let (tag, content, _) = read_tlv(value)?;
+
+    let oid = decode_oid(oid)?;
+    let name = match oid.as_str() {
+        "2.5.4.3" => "CN",
+        "2.5.4.6" => "C",
+        "2.5.4.7" => "L",
+        "2.5.4.8" => "ST",
+        "2.5.4.9" => "STREET",
+        "2.5.4.10" => "O",
+        "2.5.4.11" => "OU",
+        "0.9.2342.19200300.100.1.1" => "UID",
+        "0.9.2342.19200300.100.1.25" => "DC",
+        other => other,
+    };
+
+    // UTF8String, PrintableString, TeletexString and IA5String, anything else is hex encoded
+    let value = match tag {
+        0x0c | 0x13 | 0x14 | 0x16 => escape(&String::from_utf8_lossy(content)),
+        _ => value.iter().fold(String::from("#"), |mut hex, b| {
+            let _ = write!(hex, "{:02x}", b);
+            hex
+        }),
+    };
+
+    Some(format!("{}={}", name, value))
+}
+
+fn escape(value: &str) -> String {
+    let last = value.chars().count().saturating_sub(1);
+    let mut escaped = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        let leading = i == 0 && (c == ' ' || c == '#');
+        let trailing = i == last && c == ' ';
+        if leading || trailing || matches!(c, '"' | '+' | ',' | ';' | '<' | '>' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn decode_oid(oid: &[u8]) -> Option<String> {
+    let (&first, rest) = oid.split_first()?;
+    let mut dotted = format!("{}.{}", first / 40, first % 40);
+
+    let mut arc: u64 = 0;
+    for &b in rest {
+        arc = arc.checked_mul(128)? | u64::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            let _ = write!(dotted, ".{}", arc);
+            arc = 0;
+        }
+    }
+
+    Some(dotted)
+}
+
+// One DER element: its tag, its content and the input that follows it
+fn read_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, input) = input.split_first()?;
+
+    let (len, input) = if first < 0x80 {
+        (first as usize, input)
+    } else {
+        let octets = (first & 0x7f) as usize;
+        if octets == 0 || octets > 4 {
+            return None;
+        }
+        let len = input
+            .get(..octets)?
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | *b as usize);
+        (len, &input[octets..])
+    };
+
+    Some((tag, input.get(..len)?, &input[len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::string::{BmpString, PrintableString};
+    use rcgen::{CertificateParams, DistinguishedName, DnType, DnValue, KeyPair};
+
+    use super::*;
+
+    fn certificate(entries: Vec<(DnType, DnValue)>) -> CertificateDer<'static> {
+        let mut name = DistinguishedName::new();
+        for (ty, value) in entries {
+            name.push(ty, value);
+        }
+
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.distinguished_name = name;
+        params
+            .self_signed(&KeyPair::generate().unwrap())
+            .unwrap()
+            .der()
+            .clone()
+    }
+
+    // DER element with a short or long form length
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match content.len() {
+            len @ 0..0x80 => out.push(len as u8),
+            len @ 0x80..0x100 => out.extend([0x81, len as u8]),
+            len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        out.extend_from_slice(content);
+        out
+    }
+
+    // Certificate skeleton around `name`, with just enough structure for `subject`
+    fn certificate_with_subject(name: &[u8]) -> Vec<u8> {
+        let version = tlv(0xa0, &tlv(0x02, &[2]));
+        let serial = tlv(0x02, &[1]);
+        let algorithm = tlv(SEQUENCE, &[]);
+        let issuer = tlv(SEQUENCE, &[]);
+        let validity = tlv(SEQUENCE, &[]);
+        let subject = tlv(SEQUENCE, name);
+
+        let tbs = [version, serial, algorithm, issuer, validity, subject].concat();
+        tlv(SEQUENCE, &tlv(SEQUENCE, &tbs))
+    }
+
+    // `AttributeTypeAndValue` for the attribute type `oid`, given in DER
+    fn attribute(oid: &[u8], value: Vec<u8>) -> Vec<u8> {
+        tlv(SEQUENCE, &[tlv(OID, oid), value].concat())
+    }
+
+    const CN: &[u8] = &[0x55, 0x04, 0x03];
+    const O: &[u8] = &[0x55, 0x04, 0x0a];
+    const OU: &[u8] = &[0x55, 0x04, 0x0b];
+
+    #[test]
+    fn generated_certificate_subject() {
+        let cert = certificate(vec![
+            (DnType::CountryName, "NL".into()),
+            (DnType::OrganizationName, "Example".into()),
+            (DnType::CommonName, "billing".into()),
+        ]);
+
+        assert_eq!(subject(&cert).as_deref(), Some("CN=billing,O=Example,C=NL"));
+    }
+
+    #[test]
+    fn string_types() {
+        let cert = certificate(vec![
+            (
+                DnType::CountryName,
+                DnValue::PrintableString(PrintableString::try_from("NL").unwrap()),
+            ),
+            (DnType::CommonName, DnValue::Utf8String("bücher".into())),
+        ]);
+
+        assert_eq!(subject(&cert).as_deref(), Some("CN=bücher,C=NL"));
+    }
+
+    #[test]
+    fn escapes_special_characters() {
+        let cert = certificate(vec![
+            (DnType::OrganizationName, "Example, Inc.".into()),
+            (DnType::CommonName, "#a+b=c; <d> \\\"e\" ".into()),
+        ]);
+
+        assert_eq!(
+            subject(&cert).as_deref(),
+            Some(r#"CN=\#a\+b=c\; \<d\> \\\"e\"\ ,O=Example\, Inc."#)
+        );
+    }
+
+    #[test]
+    fn unknown_attribute_types_use_the_dotted_oid() {
+        let email = DnType::CustomDnType(vec![1, 2, 840, 113549, 1, 9, 1]);
+        let cert = certificate(vec![
+            (
+                email,
+                DnValue::Ia5String("a@example.com".try_into().unwrap()),
+            ),
+            (DnType::CommonName, "a".into()),
+        ]);
+
+        assert_eq!(
+            subject(&cert).as_deref(),
+            Some("CN=a,1.2.840.113549.1.9.1=a@example.com")
+        );
+    }
+
+    #[test]
+    fn non_string_values_are_hex_encoded() {
+        let cert = certificate(vec![(
+            DnType::CommonName,
+            DnValue::BmpString(BmpString::try_from("ab").unwrap()),
+        )]);
+        assert_eq!(subject(&cert).as_deref(), Some("CN=#1e0400610062"));
+
+        let name = tlv(SET, &attribute(OU, tlv(0x02, &[0x01, 0x00])));
+        let cert = certificate_with_subject(&name);
+        assert_eq!(subject(&cert).as_deref(), Some("OU=#02020100"));
+    }
+
+    #[test]
+    fn multi_valued_rdns_are_joined_with_plus() {
+        let name = [
+            tlv(SET, &attribute(O, tlv(0x0c, b"Example"))),
+            tlv(
+                SET,
+                &[
+                    attribute(CN, tlv(0x0c, b"billing")),
+                    attribute(OU, tlv(0x13, b"Payments")),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat();
+        let cert = certificate_with_subject(&name);
+
+        assert_eq!(
+            subject(&cert).as_deref(),
+            Some("CN=billing+OU=Payments,O=Example")
+        );
+    }
+
+    #[test]
+    fn empty_subject() {
+        assert_eq!(subject(&certificate_with_subject(&[])).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn long_form_lengths() {
+        let long = "x".repeat(300);
+        let name = tlv(SET, &attribute(CN, tlv(0x0c, long.as_bytes())));
+        let cert = certificate_with_subject(&name);
+
+        assert_eq!(subject(&cert), Some(format!("CN={long}")));
+    }
+
+    #[test]
+    fn truncated_input_is_rejected() {
+        let generated = certificate(vec![(DnType::CommonName, "billing".into())]).to_vec();
+        let name = tlv(SET, &attribute(CN, tlv(0x0c, b"billing")));
+        let handmade = certificate_with_subject(&name);
+
+        // The outer SEQUENCE covers the whole certificate, so every cut overruns some length
+        for cert in [generated, handmade] {
+            for len in 0..cert.len() {
+                assert_eq!(subject(&cert[..len]), None, "truncated to {len} bytes");
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_names_are_rejected() {
+        // Attribute outside a SET, OID missing, and a length running past the input
+        let misplaced = attribute(CN, tlv(0x0c, b"a"));
+        let without_oid = tlv(SET, &tlv(SEQUENCE, &tlv(0x0c, b"a")));
+        let overlong = tlv(SET, &[SEQUENCE, 0x7f, 0x06]);
+
+        for name in [misplaced, without_oid, overlong] {
+            assert_eq!(subject(&certificate_with_subject(&name)), None);
+        }
+        assert_eq!(subject(&[SEQUENCE, 0x85, 1, 2, 3, 4, 5]), None);
+    }
+
+    #[test]
+    fn identity_uses_the_leaf_certificate() {
+        let leaf = certificate(vec![(DnType::CommonName, "leaf".into())]);
+        let ca = certificate(vec![(DnType::CommonName, "ca".into())]);
+
+        assert_eq!(
+            ClientIdentity::from_peer_certificates(&[leaf, ca]),
+            Some(ClientIdentity {
+                subject: "CN=leaf".into()
+            })
+        );
+        assert_eq!(ClientIdentity::from_peer_certificates(&[]), None);
+    }
+}
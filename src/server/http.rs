@@ -1,14 +1,97 @@
 // src/server/http.rs
-use crate::ADDR;
 use axum::Router;
+use futures_util::FutureExt;
+use futures_util::future::{BoxFuture, try_join_all};
+use std::fmt::Display;
+use std::io;
 use std::net::SocketAddr;
+use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 
-pub async fn serve_http(app: Router, port: u16) {
-    let addr = SocketAddr::from((ADDR, port));
-    let listener = TcpListener::bind(addr).await.unwrap();
+use crate::server::connections::ConnectionCounter;
+use crate::server::listener::{SocketOptions, bind_all};
+use crate::server::shutdown::{log_drained, shutdown_signal};
+#[cfg(unix)]
+use crate::server::unix::{UnixSocketConfig, bind_unix};
 
-    println!("-> Listening on http://{}", addr);
+pub async fn serve_http(
+    app: Router,
+    addrs: &[SocketAddr],
+    #[cfg(unix)] unix_socket: Option<&UnixSocketConfig>,
+    socket: &SocketOptions,
+    drain_timeout: Duration,
+) {
+    let listeners = bind_all(addrs, socket).unwrap_or_else(|e| exit_with(e));
+    let connections = ConnectionCounter::default();
+    let shutdown = CancellationToken::new();
 
-    axum::serve(listener, app).await.unwrap();
+    let mut servers: Vec<BoxFuture<'static, io::Result<()>>> = listeners
+        .into_iter()
+        .map(|listener| {
+            let listener = TcpListener::from_std(listener).unwrap_or_else(|e| exit_with(e));
+            let addr = listener.local_addr().unwrap_or_else(|e| exit_with(e));
+            println!("-> Listening on http://{}", addr);
+
+            axum::serve(connections.track(listener), app.clone())
+                .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+                .into_future()
+                .boxed()
+        })
+        .collect();
+
+    #[cfg(unix)]
+    if let Some(config) = unix_socket {
+        let listener = bind_unix(config).unwrap_or_else(|e| exit_with(e));
+        println!("-> Listening on unix:{}", config.path.display());
+
+        servers.push(
+            axum::serve(connections.track(listener), app.clone())
+                .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+                .into_future()
+                .boxed(),
+        );
+    }
+
+    let mut servers = try_join_all(servers);
+    tokio::select! {
+        result = &mut servers => {
+            if let Err(err) = result {
+                exit_with(format!("Server failed: {}", err));
+            }
+            return;
+        }
+        _ = shutdown_signal() => {}
+    }
+
+    let open = connections.count();
+    println!(
+        "Shutting down, draining {} connection(s) for up to {}s",
+        open,
+        drain_timeout.as_secs()
+    );
+
+    shutdown.cancel();
+
+    // The servers only finish once every connection has, a connection may still be counted
+    // for a moment while its task winds down
+    let (remaining, result) = match tokio::time::timeout(drain_timeout, servers).await {
+        Ok(result) => (0, result),
+        Err(_) => (connections.count(), Ok(Vec::new())),
+    };
+    log_drained(open, remaining);
+
+    #[cfg(unix)]
+    if let Some(config) = unix_socket {
+        let _ = std::fs::remove_file(&config.path);
+    }
+
+    if let Err(err) = result {
+        exit_with(format!("Server failed while draining: {}", err));
+    }
+}
+
+fn exit_with(err: impl Display) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(1);
 }
@@ -0,0 +1,200 @@
+use std::io;
+use std::net::UdpSocket;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::Router;
+use axum::body::Body;
+use axum::http::{HeaderValue, Response, header};
+use axum::middleware::map_response;
+use bytes::{Buf, Bytes};
+use h3::error::Code;
+use h3::server::RequestResolver;
+use http_body_util::BodyExt;
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{Endpoint, EndpointConfig, Incoming, TokioRuntime};
+use rustls::ServerConfig;
+use rustls::pki_types::CertificateDer;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tower_service::Service;
+
+use crate::error::{ImageServerError, Result};
+use crate::server::client_cert::ClientIdentity;
+
+/// How long, in seconds, clients may remember the HTTP/3 endpoint advertised through `Alt-Svc`
+const ALT_SVC_MAX_AGE: u32 = 86_400;
+
+/// QUIC configuration built from the HTTPS one, offering only `h3` through ALPN
+pub fn quic_config(mut config: ServerConfig) -> Result<quinn::ServerConfig> {
+    config.alpn_protocols = vec![b"h3".to_vec()];
+
+    let crypto = QuicServerConfig::try_from(config).map_err(|_| {
+        ImageServerError::TlsError(
+            "HTTP/3 requires the TLS13_AES_128_GCM_SHA256 cipher suite".to_string(),
+        )
+    })?;
+
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// A QUIC endpoint on every socket
+pub fn endpoints(
+    sockets: Vec<UdpSocket>,
+    config: quinn::ServerConfig,
+) -> io::Result<Vec<Endpoint>> {
+    sockets
+        .into_iter()
+        .map(|socket| {
+            Endpoint::new(
+                EndpointConfig::default(),
+                Some(config.clone()),
+                socket,
+                Arc::new(TokioRuntime),
+            )
+        })
+        .collect()
+}
+
+/// Add an `Alt-Svc` header to every response of `app`, pointing clients at HTTP/3 on `port`
+pub fn advertise(app: Router, port: u16) -> Router {
+    let alt_svc = HeaderValue::try_from(format!("h3=\":{}\"; ma={}", port, ALT_SVC_MAX_AGE))
+        .expect("Alt-Svc is a valid header value");
+
+    app.layer(map_response(move |mut response: Response<Body>| {
+        let alt_svc = alt_svc.clone();
+        async move {
+            response.headers_mut().insert(header::ALT_SVC, alt_svc);
+            response
+        }
+    }))
+}
+
+/// Serve `app` over HTTP/3 until `shutdown` is cancelled.
+///
+/// Open connections are then sent a GOAWAY and get up to `drain_timeout` to finish the requests
+/// they already started.
+pub async fn serve_h3(
+    endpoint: Endpoint,
+    app: Router,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
+) -> io::Result<()> {
+    loop {
+        let incoming = tokio::select! {
+            incoming = endpoint.accept() => incoming,
+            _ = shutdown.cancelled() => break,
+        };
+        let Some(incoming) = incoming else {
+            break;
+        };
+
+        tokio::spawn(serve_connection(incoming, app.clone(), shutdown.clone()));
+    }
+
+    // No server config refuses new handshakes while the open connections drain
+    endpoint.set_server_config(None);
+    let _ = tokio::time::timeout(drain_timeout, endpoint.wait_idle()).await;
+    endpoint.close(0u32.into(), b"shutting down");
+
+    Ok(())
+}
+
+async fn serve_connection(incoming: Incoming, app: Router, shutdown: CancellationToken) {
+    let Ok(connection) = incoming.await else {
+        return;
+    };
+
+    let identity = connection
+        .peer_identity()
+        .and_then(|certs| certs.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .and_then(|certs| ClientIdentity::from_peer_certificates(&certs));
+
+    let Ok(mut connection) =
+        h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(connection)).await
+    else {
+        return;
+    };
+
+    let mut requests = JoinSet::new();
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            resolver = connection.accept() => match resolver {
+                Ok(Some(resolver)) => {
+                    requests.spawn(serve_request(resolver, app.clone(), identity.clone()));
+                }
+                // The client closing the connection ends up here as an error as well
+                Ok(None) | Err(_) => break,
+            },
+            Some(_) = requests.join_next(), if !requests.is_empty() => {}
+            // Dropping the connection would discard responses still being sent, clients close
+            // it themselves after the GOAWAY once everything has arrived
+            _ = shutdown.cancelled(), if !closing => {
+                closing = true;
+                let _ = connection.shutdown(0).await;
+            }
+        }
+    }
+
+    while requests.join_next().await.is_some() {}
+}
+
+async fn serve_request(
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    mut app: Router,
+    identity: Option<ClientIdentity>,
+) {
+    let Ok((request, stream)) = resolver.resolve_request().await else {
+        return;
+    };
+    let (mut send, recv) = stream.split();
+
+    let body = futures_util::stream::unfold(Some(recv), |recv| async move {
+        let mut recv = recv?;
+        match recv.recv_data().await {
+            Ok(Some(mut data)) => Some((Ok(data.copy_to_bytes(data.remaining())), Some(recv))),
+            Ok(None) => None,
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+
+    let mut request = request.map(|()| Body::from_stream(body));
+    if let Some(identity) = identity {
+        request.extensions_mut().insert(identity);
+    }
+
+    let response = match app.call(request).await {
+        Ok(response) => response,
+        Err(never) => match never {},
+    };
+    let (parts, mut body) = response.into_parts();
+
+    if send
+        .send_response(Response::from_parts(parts, ()))
+        .await
+        .is_err()
+    {
+        return;
+    }
+
+    while let Some(frame) = body.frame().await {
+        let Ok(frame) = frame else {
+            send.stop_stream(Code::H3_INTERNAL_ERROR);
+            return;
+        };
+
+        let sent = match frame.into_data() {
+            Ok(data) => send.send_data(data).await,
+            Err(frame) => match frame.into_trailers() {
+                Ok(trailers) => send.send_trailers(trailers).await,
+                Err(_) => Ok(()),
+            },
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+
+    let _ = send.finish().await;
+}
@@ -1,31 +1,156 @@
 use axum::Router;
+use futures_util::FutureExt;
+use futures_util::future::{BoxFuture, try_join_all};
+use std::io;
 use std::net::SocketAddr;
 
+use axum_server::Handle;
 use axum_server::tls_rustls::RustlsConfig;
-use rustls::crypto::aws_lc_rs;
-use std::path::PathBuf;
-use tokio::net::TcpSocket;
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "http3")]
+use tokio_util::sync::CancellationToken;
 
-use crate::ADDR;
+use crate::server::client_cert::ClientCertAcceptor;
+#[cfg(feature = "http3")]
+use crate::server::http3::{advertise, endpoints, quic_config, serve_h3};
+#[cfg(feature = "http3")]
+use crate::server::listener::bind_udp_all;
+use crate::server::listener::{SocketOptions, bind_all};
+use crate::server::redirect::{RedirectConfig, redirect_app};
+use crate::server::reload::spawn_cert_reload;
+use crate::server::shutdown::{log_drained, shutdown_signal};
+use crate::server::sni::host_dirs;
+use crate::server::tls::{TlsOptions, server_config};
 
-pub async fn serve_https(app: Router, port: u16, cert_path: PathBuf) {
-    let cert_dir = cert_path;
+pub async fn serve_https(
+    app: Router,
+    addrs: &[SocketAddr],
+    socket: &SocketOptions,
+    tls: TlsOptions,
+    redirect: Option<RedirectConfig>,
+    drain_timeout: Duration,
+) {
+    let server_config = server_config(&tls).unwrap_or_else(|e| exit_with(e));
+    #[cfg(feature = "http3")]
+    let quic = quic_config(server_config.clone()).unwrap_or_else(|e| exit_with(e));
+    let config = RustlsConfig::from_config(Arc::new(server_config));
 
-    let config = RustlsConfig::from_pem_file(cert_dir.join("cert.pem"), cert_dir.join("key.pem"))
-        .await
-        .expect("Unable to setup TLS Config");
+    if let Some(sni_dir) = &tls.sni_dir {
+        for (host, dir) in host_dirs(sni_dir).unwrap_or_default() {
+            println!("-> Certificate for {} from {}", host, dir.display());
+        }
+    }
 
-    let _ = aws_lc_rs::default_provider().install_default();
+    let listeners = bind_all(addrs, socket).unwrap_or_else(|e| exit_with(e));
+    // HTTP/3 listens on the same addresses and ports over UDP
+    #[cfg(feature = "http3")]
+    let endpoints = bind_udp_all(addrs)
+        .and_then(|sockets| endpoints(sockets, quic))
+        .unwrap_or_else(|e| exit_with(e));
+    let redirect_listeners = match &redirect {
+        Some(redirect) => bind_all(&redirect.addrs, socket).unwrap_or_else(|e| exit_with(e)),
+        None => Vec::new(),
+    };
 
-    let addr = SocketAddr::from((ADDR, port));
-    let socket = TcpSocket::new_v4().unwrap();
+    let tcp_config = config.clone();
+    #[cfg(feature = "http3")]
+    let quic_endpoints = endpoints.clone();
+    spawn_cert_reload(tls, move |server_config| {
+        #[cfg(feature = "http3")]
+        match quic_config(server_config.clone()) {
+            Ok(quic) => {
+                for endpoint in &quic_endpoints {
+                    endpoint.set_server_config(Some(quic.clone()));
+                }
+            }
+            Err(err) => eprintln!("Unable to reload the HTTP/3 certificate: {}", err),
+        }
+        tcp_config.reload_from_config(Arc::new(server_config));
+    });
 
-    socket.set_send_buffer_size(524_288).unwrap();
-    socket.set_recv_buffer_size(524_288).unwrap();
-    socket.set_nodelay(true).unwrap();
+    // One handle for every listener, so the drain covers connections from all of them
+    let handle = Handle::new();
+    #[cfg(feature = "http3")]
+    let quic_shutdown = CancellationToken::new();
 
-    axum_server::bind_rustls(addr, config)
-        .serve(app.into_make_service())
-        .await
-        .unwrap()
+    let mut servers: Vec<BoxFuture<'static, io::Result<()>>> = listeners
+        .into_iter()
+        .map(|listener| {
+            let addr = listener.local_addr().unwrap_or_else(|e| exit_with(e));
+            println!("-> Listening on https://{}", addr);
+
+            let app = app.clone();
+            #[cfg(feature = "http3")]
+            let app = advertise(app, addr.port());
+
+            axum_server::from_tcp(listener)
+                .acceptor(ClientCertAcceptor::new(config.clone()))
+                .handle(handle.clone())
+                .serve(app.into_make_service())
+                .boxed()
+        })
+        .collect();
+
+    #[cfg(feature = "http3")]
+    for endpoint in endpoints {
+        println!(
+            "-> Listening on https://{} (HTTP/3)",
+            endpoint.local_addr().unwrap_or_else(|e| exit_with(e))
+        );
+
+        servers.push(serve_h3(endpoint, app.clone(), quic_shutdown.clone(), drain_timeout).boxed());
+    }
+
+    if let Some(redirect) = &redirect {
+        let redirect_app = redirect_app(redirect);
+
+        for listener in redirect_listeners {
+            println!(
+                "-> Redirecting http://{} to HTTPS",
+                listener.local_addr().unwrap_or_else(|e| exit_with(e))
+            );
+
+            servers.push(
+                axum_server::from_tcp(listener)
+                    .handle(handle.clone())
+                    .serve(redirect_app.clone().into_make_service())
+                    .boxed(),
+            );
+        }
+    }
+
+    let mut servers = try_join_all(servers);
+    tokio::select! {
+        result = &mut servers => {
+            if let Err(err) = result {
+                exit_with(format!("Server failed: {}", err));
+            }
+            return;
+        }
+        _ = shutdown_signal() => {}
+    }
+
+    let open = handle.connection_count();
+    println!(
+        "Shutting down, draining {} connection(s) for up to {}s",
+        open,
+        drain_timeout.as_secs()
+    );
+
+    #[cfg(feature = "http3")]
+    quic_shutdown.cancel();
+    handle.graceful_shutdown(Some(drain_timeout));
+    let result = servers.await;
+    log_drained(open, handle.connection_count());
+
+    if let Err(err) = result {
+        exit_with(format!("Server failed while draining: {}", err));
+    }
+}
+
+fn exit_with(err: impl Display) -> ! {
+    eprintln!("{}", err);
+    std::process::exit(1);
 }
@@ -0,0 +1,121 @@
+use std::io;
+#[cfg(feature = "http3")]
+use std::net::UdpSocket;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+
+/// Tuning of the listening TCP sockets.
+///
+/// Everything is set before the socket starts listening, accepted connections inherit it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// `SO_SNDBUF` in bytes, `None` leaves it to the OS and its autotuning
+    pub send_buffer_size: Option<usize>,
+    /// `SO_RCVBUF` in bytes, `None` leaves it to the OS and its autotuning
+    pub recv_buffer_size: Option<usize>,
+    /// `TCP_NODELAY`, sends small writes right away instead of batching them
+    pub nodelay: bool,
+    /// Idle time before TCP keepalive probes are sent, `None` disables keepalive
+    pub keepalive: Option<Duration>,
+    /// Connections the kernel queues until they are accepted
+    pub backlog: i32,
+    /// `SO_REUSEPORT`, lets several processes bind the same address and share its connections
+    #[cfg(unix)]
+    pub reuse_port: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            nodelay: true,
+            keepalive: None,
+            backlog: 1024,
+            #[cfg(unix)]
+            reuse_port: false,
+        }
+    }
+}
+
+/// Bind a listening socket for every address, tuned with `options`.
+///
+/// An unspecified IPv6 address (`[::]`) also accepts IPv4 connections, unless `0.0.0.0` is bound
+/// on the same port as well, in which case each family gets its own socket.
+pub fn bind_all(addrs: &[SocketAddr], options: &SocketOptions) -> io::Result<Vec<TcpListener>> {
+    addrs
+        .iter()
+        .map(|addr| {
+            bind(*addr, dual_stack(addr, addrs), options)
+                .map_err(|e| io::Error::new(e.kind(), format!("unable to bind {}: {}", addr, e)))
+        })
+        .collect()
+}
+
+/// Bind a UDP socket for every address, for QUIC. `[::]` is dual-stack as in [`bind_all`]
+#[cfg(feature = "http3")]
+pub fn bind_udp_all(addrs: &[SocketAddr]) -> io::Result<Vec<UdpSocket>> {
+    addrs
+        .iter()
+        .map(|addr| {
+            bind_udp(*addr, dual_stack(addr, addrs)).map_err(|e| {
+                io::Error::new(e.kind(), format!("unable to bind udp {}: {}", addr, e))
+            })
+        })
+        .collect()
+}
+
+fn dual_stack(addr: &SocketAddr, addrs: &[SocketAddr]) -> bool {
+    addr.is_ipv6()
+        && addr.ip().is_unspecified()
+        && !addrs.iter().any(|other| {
+            other.is_ipv4() && other.ip().is_unspecified() && other.port() == addr.port()
+        })
+}
+
+fn bind(addr: SocketAddr, dual_stack: bool, options: &SocketOptions) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(options.reuse_port)?;
+
+    // The receive buffer has to be set before listening to allow a large TCP window
+    if let Some(size) = options.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = options.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    socket.set_tcp_nodelay(options.nodelay)?;
+    if let Some(idle) = options.keepalive {
+        socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.listen(options.backlog)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into())
+}
+
+#[cfg(feature = "http3")]
+fn bind_udp(addr: SocketAddr, dual_stack: bool) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into())
+}
@@ -1,6 +1,33 @@
-
 #[cfg(feature = "tls")]
 pub mod https;
 
+#[cfg(feature = "tls")]
+pub mod tls;
+
+#[cfg(feature = "tls")]
+pub mod sni;
+
+#[cfg(feature = "tls")]
+pub mod client_cert;
+
+#[cfg(feature = "tls")]
+pub mod reload;
+
+#[cfg(feature = "tls")]
+pub mod redirect;
+
+#[cfg(feature = "http3")]
+pub mod http3;
+
+#[cfg(not(feature = "tls"))]
+pub mod http;
+
+pub mod listener;
+
+#[cfg(all(unix, not(feature = "tls")))]
+pub mod unix;
+
+pub mod shutdown;
+
 #[cfg(not(feature = "tls"))]
-pub mod http;
\ No newline at end of file
+pub mod connections;
@@ -0,0 +1,125 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+
+/// Plain HTTP listener that runs next to the HTTPS one
+#[derive(Debug, Clone)]
+pub struct RedirectConfig {
+    pub addrs: Vec<SocketAddr>,
+    /// Host, optionally with a port, that redirects point to. The request's `Host` when unset
+    pub https_host: Option<String>,
+    /// Port of the HTTPS listener, added to the redirect unless it is 443
+    pub https_port: u16,
+    /// Directory of ACME HTTP-01 challenge files, served instead of redirected
+    pub acme_challenge_dir: Option<PathBuf>,
+}
+
+/// Router that answers every request with a `308` to the same path over HTTPS, except for ACME
+/// challenges when a challenge directory is configured
+pub fn redirect_app(config: &RedirectConfig) -> Router {
+    let config = Arc::new(config.clone());
+
+    let app = match config.acme_challenge_dir {
+        Some(_) => Router::new().route("/.well-known/acme-challenge/{token}", get(acme_challenge)),
+        None => Router::new(),
+    };
+
+    app.fallback(redirect).with_state(config)
+}
+
+async fn redirect(
+    State(config): State<Arc<RedirectConfig>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Response {
+    let host = match &config.https_host {
+        Some(host) => Some(host.as_str()),
+        None => headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(strip_port),
+    };
+
+    let Some(host) = host.filter(|host| !host.is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+
+    let port = if has_port(host) || config.https_port == 443 {
+        String::new()
+    } else {
+        format!(":{}", config.https_port)
+    };
+    let path = uri.path_and_query().map_or("/", |path| path.as_str());
+
+    match HeaderValue::try_from(format!("https://{}{}{}", host, port, path)) {
+        Ok(location) => (
+            StatusCode::PERMANENT_REDIRECT,
+            [(header::LOCATION, location)],
+        )
+            .into_response(),
+        Err(_) => (StatusCode::BAD_REQUEST, "Invalid Host header").into_response(),
+    }
+}
+
+async fn acme_challenge(
+    State(config): State<Arc<RedirectConfig>>,
+    Path(token): Path<String>,
+) -> Response {
+    // Tokens are base64url, which also keeps the lookup inside the challenge directory
+    let valid_token = !token.is_empty()
+        && token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+
+    let Some(dir) = config.acme_challenge_dir.as_ref().filter(|_| valid_token) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    match tokio::fs::read(dir.join(&token)).await {
+        Ok(key_authorization) => (
+            [(header::CONTENT_TYPE, "application/octet-stream")],
+            key_authorization,
+        )
+            .into_response(),
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Host and port of `base_url` with any scheme, credentials and path dropped, e.g.
+/// `cdn.example.com` for `https://cdn.example.com/img`. A URL without a scheme is read as HTTPS
+pub fn base_authority(base_url: &str) -> Option<String> {
+    let url = if base_url.contains("://") {
+        base_url.to_string()
+    } else {
+        format!("https://{}", base_url)
+    };
+    let uri: Uri = url.parse().ok()?;
+    let authority = uri.authority()?;
+
+    Some(match authority.port_u16() {
+        Some(port) => format!("{}:{}", authority.host(), port),
+        None => authority.host().to_string(),
+    })
+}
+
+// `example.com:80` -> `example.com`, `[::1]:80` -> `[::1]`
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if is_port(port) => name,
+        _ => host,
+    }
+}
+
+fn has_port(host: &str) -> bool {
+    host.rsplit_once(':').is_some_and(|(_, port)| is_port(port))
+}
+
+fn is_port(port: &str) -> bool {
+    !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit())
+}
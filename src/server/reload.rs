@@ -0,0 +1,122 @@
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use rustls::ServerConfig;
+
+use crate::error::ImageServerError;
+use crate::server::sni::host_dirs;
+use crate::server::tls::{TlsOptions, server_config};
+
+/// Rebuild the configuration from the files named in `options` when they change on disk, checked
+/// every `cert_reload_interval`, or on SIGHUP, and hand it to `reload`.
+///
+/// Connections already established keep the certificate they were accepted with. A reload that
+/// fails, e.g. because only one of the files has been replaced so far, keeps the current
+/// certificate and is retried on the next change.
+pub fn spawn_cert_reload<F>(options: TlsOptions, reload: F)
+where
+    F: Fn(ServerConfig) + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut last_seen = fingerprint(&options);
+        let mut ticker = options.cert_reload_interval.map(tokio::time::interval);
+        let mut hangup = hangup_signal();
+
+        loop {
+            let forced = tokio::select! {
+                _ = tick(&mut ticker) => false,
+                _ = recv(&mut hangup) => true,
+            };
+
+            let current = fingerprint(&options);
+            if !forced && current == last_seen {
+                continue;
+            }
+            last_seen = current;
+
+            let rebuild = options.clone();
+            let rebuilt = tokio::task::spawn_blocking(move || server_config(&rebuild))
+                .await
+                .unwrap_or_else(|e| Err(ImageServerError::Internal(e.to_string())));
+
+            match rebuilt {
+                Ok(server_config) => {
+                    reload(server_config);
+                    println!(
+                        "-> Reloaded TLS certificate from {}",
+                        options.cert_path.display()
+                    );
+                }
+                Err(err) => eprintln!(
+                    "Unable to reload TLS certificate from {}, keeping the current one: {}",
+                    options.cert_path.display(),
+                    err
+                ),
+            }
+        }
+    });
+}
+
+// Size and modification time of every file the configuration is built from, following the
+// symlinks secret mounts swap out. Hostname folders added to or removed from `sni_dir` change
+// the list itself
+fn fingerprint(options: &TlsOptions) -> Vec<(PathBuf, Option<(u64, SystemTime)>)> {
+    let sni_files = options
+        .sni_dir
+        .iter()
+        .flat_map(|sni_dir| host_dirs(sni_dir).unwrap_or_default())
+        .flat_map(|(_, dir)| [dir.join("cert.pem"), dir.join("key.pem")]);
+
+    [options.cert_file(), options.key_file()]
+        .into_iter()
+        .chain(options.client_ca.clone())
+        .chain(sni_files)
+        .map(|path| {
+            let modified = std::fs::metadata(&path)
+                .ok()
+                .and_then(|metadata| Some((metadata.len(), metadata.modified().ok()?)));
+            (path, modified)
+        })
+        .collect()
+}
+
+async fn tick(ticker: &mut Option<tokio::time::Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+type Hangup = Option<tokio::signal::unix::Signal>;
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(unix)]
+fn hangup_signal() -> Hangup {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    signal(SignalKind::hangup())
+        .inspect_err(|err| eprintln!("Unable to listen for SIGHUP: {}", err))
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn hangup_signal() -> Hangup {}
+
+#[cfg(unix)]
+async fn recv(hangup: &mut Hangup) {
+    match hangup {
+        Some(hangup) => {
+            hangup.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn recv(_: &mut Hangup) {
+    std::future::pending().await
+}
@@ -0,0 +1,49 @@
+//! Graceful shutdown on SIGINT / SIGTERM.
+//!
+//! Listeners stop accepting as soon as a signal arrives, connections that are still transferring
+//! get up to the drain timeout to finish before the process exits.
+
+/// Resolves once the process is asked to stop with Ctrl+C / SIGINT or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            eprintln!("Unable to listen for Ctrl+C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(err) => {
+                eprintln!("Unable to listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Summarize a drain that started with `open` connections and ended with `remaining` still open
+pub fn log_drained(open: usize, remaining: usize) {
+    if remaining == 0 {
+        println!("Drained {} connection(s), shutting down", open);
+    } else {
+        println!(
+            "Drain timeout reached: drained {} of {} connection(s), closing the rest",
+            open.saturating_sub(remaining),
+            open
+        );
+    }
+}
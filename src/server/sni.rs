@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+
+/// Certificate selection by the hostname clients send through SNI.
+///
+/// Clients asking for a hostname without its own certificate, or not sending SNI at all, get the
+/// default certificate.
+#[derive(Debug)]
+pub struct SniResolver {
+    default: Arc<CertifiedKey>,
+    hosts: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    pub fn new(default: CertifiedKey, hosts: HashMap<String, CertifiedKey>) -> Self {
+        Self {
+            default: Arc::new(default),
+            hosts: hosts
+                .into_iter()
+                .map(|(host, cert)| (host.to_ascii_lowercase(), Arc::new(cert)))
+                .collect(),
+        }
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let cert = client_hello
+            .server_name()
+            .and_then(|name| self.hosts.get(&name.to_ascii_lowercase()))
+            .unwrap_or(&self.default);
+
+        Some(cert.clone())
+    }
+}
+
+/// Subdirectories of `dir` named after the hostname whose `cert.pem` and `key.pem` they hold,
+/// sorted by name. Hidden entries, like the `..data` links of Kubernetes secret mounts, are skipped
+pub fn host_dirs(dir: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut hosts = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(host) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if host.starts_with('.') || !path.is_dir() {
+            continue;
+        }
+        hosts.push((host.to_string(), path));
+    }
+
+    hosts.sort();
+    Ok(hosts)
+}
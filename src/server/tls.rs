@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::crypto::{CryptoProvider, aws_lc_rs};
+use rustls::pki_types::pem::{self, PemObject};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::danger::ClientCertVerifier;
+use rustls::sign::CertifiedKey;
+use rustls::version::{TLS12, TLS13};
+use rustls::{InconsistentKeys, RootCertStore, ServerConfig, SupportedProtocolVersion};
+
+use crate::error::{ImageServerError, Result};
+use crate::server::sni::{SniResolver, host_dirs};
+
+/// Certificate and TLS settings of the HTTPS listeners
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    /// Folder containing `cert.pem` and `key.pem`, served to clients without a matching SNI host
+    pub cert_path: PathBuf,
+    /// Folder of per-hostname subfolders, each containing its own `cert.pem` and `key.pem`
+    pub sni_dir: Option<PathBuf>,
+    /// How often to check the certificate for changes, `None` only reloads on SIGHUP
+    pub cert_reload_interval: Option<Duration>,
+    /// PEM bundle of CAs client certificates are verified against, enables mutual TLS
+    pub client_ca: Option<PathBuf>,
+    pub client_auth: ClientAuth,
+    pub min_version: TlsVersion,
+    /// Cipher suite names such as `TLS13_AES_256_GCM_SHA384`, the provider defaults when empty
+    pub cipher_suites: Vec<String>,
+    /// ALPN protocols in order of preference
+    pub alpn: Vec<String>,
+}
+
+/// Oldest TLS version accepted from clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+/// Whether clients must present a certificate when `client_ca` is set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuth {
+    /// Handshakes without a valid client certificate are rejected
+    Required,
+    /// Anonymous clients are accepted, a certificate that is presented must still be valid
+    Optional,
+}
+
+impl TlsOptions {
+    pub fn cert_file(&self) -> PathBuf {
+        self.cert_path.join("cert.pem")
+    }
+
+    pub fn key_file(&self) -> PathBuf {
+        self.cert_path.join("key.pem")
+    }
+}
+
+/// Build the rustls configuration from the files and policy in `options`
+pub fn server_config(options: &TlsOptions) -> Result<ServerConfig> {
+    let provider = Arc::new(crypto_provider(&options.cipher_suites)?);
+    let versions: &[&SupportedProtocolVersion] = match options.min_version {
+        TlsVersion::Tls12 => &[&TLS13, &TLS12],
+        TlsVersion::Tls13 => &[&TLS13],
+    };
+
+    let default = certified_key(&options.cert_path, &provider)?;
+    let hosts = match &options.sni_dir {
+        Some(sni_dir) => sni_certs(sni_dir, &provider)?,
+        None => HashMap::new(),
+    };
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(versions)
+        .map_err(|e| ImageServerError::TlsError(format!("unusable TLS policy: {}", e)))?;
+
+    let builder = match &options.client_ca {
+        Some(client_ca) => builder.with_client_cert_verifier(client_verifier(
+            client_ca,
+            options.client_auth,
+            provider,
+        )?),
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_cert_resolver(Arc::new(SniResolver::new(default, hosts)));
+    config.alpn_protocols = alpn_protocols(&options.alpn)?;
+
+    Ok(config)
+}
+
+/// Names of the cipher suites that can be passed in [`TlsOptions::cipher_suites`]
+pub fn supported_cipher_suites() -> Vec<&'static str> {
+    aws_lc_rs::default_provider()
+        .cipher_suites
+        .iter()
+        .filter_map(|suite| suite.suite().as_str())
+        .collect()
+}
+
+// The aws-lc-rs provider, reduced to the named cipher suites in the given order
+fn crypto_provider(cipher_suites: &[String]) -> Result<CryptoProvider> {
+    let mut provider = aws_lc_rs::default_provider();
+    if cipher_suites.is_empty() {
+        return Ok(provider);
+    }
+
+    provider.cipher_suites = cipher_suites
+        .iter()
+        .map(|name| {
+            provider
+                .cipher_suites
+                .iter()
+                .find(|suite| {
+                    suite
+                        .suite()
+                        .as_str()
+                        .is_some_and(|suite| suite.eq_ignore_ascii_case(name))
+                })
+                .copied()
+                .ok_or_else(|| {
+                    ImageServerError::TlsError(format!(
+                        "unknown cipher suite {}, supported: {}",
+                        name,
+                        supported_cipher_suites().join(", ")
+                    ))
+                })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(provider)
+}
+
+// HTTP/2 and HTTP/1.1 are the only protocols the listeners speak
+fn alpn_protocols(alpn: &[String]) -> Result<Vec<Vec<u8>>> {
+    alpn.iter()
+        .map(|protocol| match protocol.as_str() {
+            "h2" | "http/1.1" => Ok(protocol.as_bytes().to_vec()),
+            other => Err(ImageServerError::TlsError(format!(
+                "unsupported ALPN protocol {}, expected h2 or http/1.1",
+                other
+            ))),
+        })
+        .collect()
+}
+
+fn client_verifier(
+    client_ca: &Path,
+    auth: ClientAuth,
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<dyn ClientCertVerifier>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(client_ca)? {
+        roots
+            .add(cert)
+            .map_err(|e| ImageServerError::TlsError(format!("{}: {}", client_ca.display(), e)))?;
+    }
+
+    let builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+    let builder = match auth {
+        ClientAuth::Required => builder,
+        ClientAuth::Optional => builder.allow_unauthenticated(),
+    };
+
+    builder
+        .build()
+        .map_err(|e| ImageServerError::TlsError(format!("{}: {}", client_ca.display(), e)))
+}
+
+// Certificate of every hostname folder in `sni_dir`
+fn sni_certs(sni_dir: &Path, provider: &CryptoProvider) -> Result<HashMap<String, CertifiedKey>> {
+    host_dirs(sni_dir)
+        .map_err(|e| ImageServerError::TlsError(format!("{}: {}", sni_dir.display(), e)))?
+        .into_iter()
+        .map(|(host, dir)| Ok((host, certified_key(&dir, provider)?)))
+        .collect()
+}
+
+// `cert.pem` and `key.pem` of `dir`, checked to belong together
+fn certified_key(dir: &Path, provider: &CryptoProvider) -> Result<CertifiedKey> {
+    let cert_file = dir.join("cert.pem");
+    let key_file = dir.join("key.pem");
+
+    let certs = load_certs(&cert_file)?;
+    let key = provider
+        .key_provider
+        .load_private_key(load_key(&key_file)?)
+        .map_err(|e| ImageServerError::TlsError(format!("{}: {}", key_file.display(), e)))?;
+
+    let certified_key = CertifiedKey::new(certs, key);
+    match certified_key.keys_match() {
+        Ok(()) | Err(rustls::Error::InconsistentKeys(InconsistentKeys::Unknown)) => {
+            Ok(certified_key)
+        }
+        Err(e) => Err(ImageServerError::TlsError(format!(
+            "{} does not match {}: {}",
+            key_file.display(),
+            cert_file.display(),
+            e
+        ))),
+    }
+}
+
+// PKCS#8, PKCS#1 (RSA) and SEC1 (EC) keys are all accepted, the first one in the file is used
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| {
+        let reason = match e {
+            pem::Error::NoItemsFound => {
+                "no private key found, expected a PKCS#8, RSA or EC key in PEM format".to_string()
+            }
+            other => other.to_string(),
+        };
+        ImageServerError::TlsError(format!("{}: {}", path.display(), reason))
+    })
+}
+
+// Leaf certificate first, followed by any intermediates
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| ImageServerError::TlsError(format!("{}: {}", path.display(), e)))?;
+
+    if certs.is_empty() {
+        return Err(ImageServerError::TlsError(format!(
+            "{}: no certificates found",
+            path.display()
+        )));
+    }
+
+    Ok(certs)
+}
@@ -0,0 +1,53 @@
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use tokio::net::UnixListener;
+
+/// Unix domain socket to serve on alongside, or instead of, TCP
+#[derive(Debug, Clone)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    /// Permission bits applied to the socket file, e.g. `0o660`
+    pub mode: u32,
+}
+
+/// Bind the socket, replacing a stale socket file left behind by a previous run
+pub fn bind_unix(config: &UnixSocketConfig) -> io::Result<UnixListener> {
+    let context =
+        |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", config.path.display(), e));
+
+    remove_stale_socket(&config.path).map_err(context)?;
+
+    let listener = UnixListener::bind(&config.path).map_err(context)?;
+    fs::set_permissions(&config.path, Permissions::from_mode(config.mode)).map_err(context)?;
+
+    Ok(listener)
+}
+
+// A socket nobody accepts connections on is left over from a crashed or killed server, anything
+// else at the path is not ours to delete
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "path exists and is not a socket",
+        ));
+    }
+
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another server is listening on this socket",
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,214 @@
+/// Number of leading bytes needed to detect any supported type.
+///
+/// Binary formats are identified within the first 32 bytes, SVG needs room for an XML prolog
+/// and comments before the root element.
+pub const SNIFF_LEN: usize = 1024;
+
+/// Image types the server is willing to serve, detected from file contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageType {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+    Avif,
+    Bmp,
+    Tiff,
+    Ico,
+    Svg,
+    Qoi,
+}
+
+impl ImageType {
+    /// Detect the type from the first bytes of a file, at most [`SNIFF_LEN`] are inspected
+    pub fn sniff(head: &[u8]) -> Option<Self> {
+        let head = &head[..head.len().min(SNIFF_LEN)];
+
+        if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if head.starts_with(b"\xff\xd8\xff") {
+            Some(Self::Jpeg)
+        } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else if head.len() >= 12 && &head[..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+            Some(Self::WebP)
+        } else if is_avif(head) {
+            Some(Self::Avif)
+        } else if head.starts_with(b"BM") && head.len() >= 14 {
+            Some(Self::Bmp)
+        } else if head.starts_with(b"II*\0") || head.starts_with(b"MM\0*") {
+            Some(Self::Tiff)
+        } else if head.len() >= 6 && head.starts_with(b"\0\0\x01\0") && head[4..6] != [0, 0] {
+            Some(Self::Ico)
+        } else if head.starts_with(b"qoif") {
+            Some(Self::Qoi)
+        } else if is_svg(head) {
+            Some(Self::Svg)
+        } else {
+            None
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Gif => "image/gif",
+            Self::WebP => "image/webp",
+            Self::Avif => "image/avif",
+            Self::Bmp => "image/bmp",
+            Self::Tiff => "image/tiff",
+            Self::Ico => "image/x-icon",
+            Self::Svg => "image/svg+xml",
+            Self::Qoi => "image/qoi",
+        }
+    }
+}
+
+// ISO-BMFF `ftyp` box with an AVIF major or compatible brand
+fn is_avif(head: &[u8]) -> bool {
+    if head.len() < 16 || &head[4..8] != b"ftyp" {
+        return false;
+    }
+
+    let box_len = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as usize;
+    let brands = &head[8..box_len.clamp(8, head.len())];
+
+    // Major brand, minor version, then compatible brands
+    brands
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .any(|(_, brand)| brand == b"avif" || brand == b"avis")
+}
+
+// XML text whose root element is `<svg`, optionally after a prolog, doctype and comments
+fn is_svg(head: &[u8]) -> bool {
+    let Ok(text) = std::str::from_utf8(trim_incomplete_utf8(head)) else {
+        return false;
+    };
+    let mut rest = text.trim_start_matches('\u{feff}').trim_start();
+
+    loop {
+        // The element name has to end there, `<svgfoo>` is some other markup
+        if let Some(after) = rest.strip_prefix("<svg") {
+            return after.starts_with(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/');
+        }
+
+        let skip_to = if rest.starts_with("<?") {
+            "?>"
+        } else if rest.starts_with("<!--") {
+            "-->"
+        } else if rest.starts_with("<!") {
+            ">"
+        } else {
+            return false;
+        };
+
+        match rest.find(skip_to) {
+            Some(end) => rest = rest[end + skip_to.len()..].trim_start(),
+            None => return false,
+        }
+    }
+}
+
+// The sniffed prefix may cut a multi-byte character in half
+fn trim_incomplete_utf8(bytes: &[u8]) -> &[u8] {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => bytes,
+        Err(err) if err.error_len().is_none() => &bytes[..err.valid_up_to()],
+        Err(_) => bytes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniff(head: &[u8]) -> Option<ImageType> {
+        ImageType::sniff(head)
+    }
+
+    #[test]
+    fn binary_signatures() {
+        let cases: &[(&[u8], ImageType)] = &[
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", ImageType::Png),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF\0", ImageType::Jpeg),
+            (b"GIF87a\x01\0\x01\0", ImageType::Gif),
+            (b"GIF89a\x01\0\x01\0", ImageType::Gif),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", ImageType::WebP),
+            (b"\0\0\0\x1cftypavif\0\0\0\0avifmif1", ImageType::Avif),
+            (b"\0\0\0\x1cftypmif1\0\0\0\0mif1avis", ImageType::Avif),
+            (b"BM\x46\0\0\0\0\0\0\0\x36\0\0\0", ImageType::Bmp),
+            (b"II*\0\x08\0\0\0", ImageType::Tiff),
+            (b"MM\0*\0\0\0\x08", ImageType::Tiff),
+            (b"\0\0\x01\0\x01\0\x10\x10", ImageType::Ico),
+            (b"qoif\0\0\0\x01\0\0\0\x01\x04\0", ImageType::Qoi),
+        ];
+
+        for (head, expected) in cases {
+            assert_eq!(sniff(head), Some(*expected), "{head:?}");
+        }
+    }
+
+    #[test]
+    fn svg_documents() {
+        let cases: &[&str] = &[
+            "<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+            "<svg>",
+            "<svg/>",
+            "<svg\n  width=\"10\">",
+            "\u{feff}<svg>",
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<svg>",
+            "<?xml version=\"1.0\"?><!-- icon --><!DOCTYPE svg PUBLIC \"-//W3C//DTD SVG 1.1//EN\" \"x\"> <svg>",
+            "\n\n  <!-- one --> <!-- two -->\n<svg>",
+        ];
+
+        for text in cases {
+            assert_eq!(sniff(text.as_bytes()), Some(ImageType::Svg), "{text:?}");
+        }
+    }
+
+    #[test]
+    fn svg_cut_by_the_sniff_window() {
+        // A multi-byte character split at the end of the window does not hide the root element
+        let mut head = "<svg>".as_bytes().to_vec();
+        head.extend(std::iter::repeat_n(b'a', SNIFF_LEN - head.len() - 1));
+        head.extend_from_slice("é".as_bytes());
+        assert_eq!(sniff(&head), Some(ImageType::Svg));
+
+        // The root element has to start within the window
+        let late = format!("<!--{}--><svg>", "x".repeat(SNIFF_LEN));
+        assert_eq!(sniff(late.as_bytes()), None);
+    }
+
+    #[test]
+    fn rejects_other_content() {
+        let cases: &[&[u8]] = &[
+            b"",
+            b"hello world",
+            b"<html><body></body></html>",
+            b"<svgx>",
+            b"<svgfoo xmlns=\"x\">",
+            b"<svg",
+            b"<!-- unterminated <svg>",
+            b"<?xml version=\"1.0\"?><html/>",
+            // Truncated headers
+            b"\x89PNG\r\n",
+            b"\xff\xd8",
+            b"GIF8",
+            b"RIFF\x24\0\0\0WEB",
+            b"\0\0\0\x1cftypavif",
+            b"BM\x46\0",
+            b"II*",
+            b"\0\0\x01\0\0\0",
+            b"qoi",
+            // An ftyp box of another brand
+            b"\0\0\0\x18ftypisom\0\0\0\0isommp41",
+        ];
+
+        for head in cases {
+            assert_eq!(sniff(head), None, "{head:?}");
+        }
+    }
+}